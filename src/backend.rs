@@ -0,0 +1,239 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::ValueEnum;
+
+/// A JavaScript runtime which can run the programs generated by `deno::eval`.
+pub trait Backend {
+    /// Whether the runtime understands TypeScript, or it needs transpiling to JavaScript first.
+    fn typescript(&self) -> bool;
+
+    /// JavaScript defining `__jsq_lines`, an async generator which reads lines from STDIN, without
+    /// ever holding more than a line in memory, and yields each one parsed as JSON.
+    fn lines(&self) -> &'static str;
+
+    /// Start running `code`, which reads its input from the STDIN of the returned process. When
+    /// `capture` is set, its STDOUT is piped back rather than inherited. Its STDERR is always
+    /// piped back, so that errors can be mapped back to SCRIPT.
+    fn spawn(&self, code: String, capture: bool) -> Result<Process>;
+}
+
+/// A running program, which may or may not be a separate OS process.
+pub struct Process {
+    pub stdin: Option<Box<dyn Write + Send>>,
+    pub stdout: Option<Box<dyn Read + Send>>,
+    pub stderr: Option<Box<dyn Read + Send>>,
+    /// The name of the file the code is run from, as it appears in stack traces.
+    pub module: String,
+    wait: Box<dyn FnOnce() -> Result<i32>>,
+}
+
+impl Process {
+    pub fn new(
+        stdin: Box<dyn Write + Send>,
+        stdout: Option<Box<dyn Read + Send>>,
+        stderr: Box<dyn Read + Send>,
+        module: String,
+        wait: impl FnOnce() -> Result<i32> + 'static,
+    ) -> Process {
+        Process {
+            stdin: Some(stdin),
+            stdout,
+            stderr: Some(stderr),
+            module,
+            wait: Box::new(wait),
+        }
+    }
+
+    /// Wait for the program to finish, returning its exit code.
+    pub fn wait(self) -> Result<i32> {
+        (self.wait)()
+    }
+}
+
+/// The runtimes which jsq can use.
+#[derive(Copy, Clone, ValueEnum)]
+pub enum Runtime {
+    Deno,
+    Node,
+    Bun,
+    #[cfg(feature = "quickjs")]
+    Quickjs,
+}
+
+/// The backend for `runtime`, defaulting to Deno, unless it isn't installed and an embedded
+/// runtime is available instead. `permissions` are Deno permission flags to run with in place of
+/// `--allow-all`, which only Deno can enforce.
+pub fn new(runtime: Option<Runtime>, permissions: Option<Vec<String>>) -> Result<Box<dyn Backend>> {
+    let sandboxed = permissions.is_some();
+    Ok(match runtime {
+        Some(Runtime::Deno) => Box::new(Subprocess::deno(permissions)),
+        _ if sandboxed && runtime.is_some() => {
+            bail!("sandboxing is only supported by deno, try --runtime deno")
+        }
+        Some(Runtime::Node) => Box::new(Subprocess::node()),
+        Some(Runtime::Bun) => Box::new(Subprocess::bun()),
+        #[cfg(feature = "quickjs")]
+        Some(Runtime::Quickjs) => Box::new(crate::quickjs::QuickJs),
+        #[cfg(feature = "quickjs")]
+        None if !sandboxed && !on_path("deno") => Box::new(crate::quickjs::QuickJs),
+        None => Box::new(Subprocess::deno(permissions)),
+    })
+}
+
+#[cfg(feature = "quickjs")]
+fn on_path(program: &str) -> bool {
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| {
+            dir.join(format!("{program}{}", std::env::consts::EXE_SUFFIX))
+                .is_file()
+        })
+    })
+}
+
+/// Reads lines from `process.stdin`, for runtimes which are compatible with Node.
+const NODE_LINES: &str = r#"
+    async function* __jsq_lines() {
+        const { createInterface } = await import("node:readline");
+        for await (const line of createInterface({ input: process.stdin, crlfDelay: Infinity })) {
+            yield JSON.parse(line);
+        }
+    }
+"#;
+
+/// A runtime which runs in a child process, reading the program from a temporary file, so that
+/// STDIN is free for input.
+pub struct Subprocess {
+    program: &'static str,
+    args: Vec<String>,
+    /// The extension for the temporary file.
+    suffix: &'static str,
+    typescript: bool,
+    lines: &'static str,
+}
+
+impl Subprocess {
+    /// Deno, with all permissions, or only `permissions` if given. Without all permissions, Deno
+    /// fails rather than prompting for more.
+    pub fn deno(permissions: Option<Vec<String>>) -> Subprocess {
+        let mut args = vec!["run".to_string(), "--no-config".to_string()];
+        match permissions {
+            Some(permissions) => {
+                args.push("--no-prompt".to_string());
+                args.extend(permissions);
+            }
+            None => args.push("--allow-all".to_string()),
+        }
+        Subprocess {
+            program: "deno",
+            args,
+            suffix: ".ts",
+            typescript: true,
+            lines: r#"
+                async function* __jsq_lines() {
+                    const decoder = new TextDecoder();
+                    // Only new text is searched for the end of a line, and the pieces of a line
+                    // are joined once, so that long lines take linear time.
+                    let pieces = [];
+                    for await (const chunk of Deno.stdin.readable) {
+                        const text = decoder.decode(chunk, { stream: true });
+                        let start = 0;
+                        for (let end; (end = text.indexOf("\n", start)) !== -1; start = end + 1) {
+                            pieces.push(text.slice(start, end));
+                            yield JSON.parse(pieces.join(""));
+                            pieces = [];
+                        }
+                        pieces.push(text.slice(start));
+                    }
+                    pieces.push(decoder.decode());
+                    const rest = pieces.join("");
+                    if (rest) {
+                        yield JSON.parse(rest);
+                    }
+                }
+            "#,
+        }
+    }
+
+    pub fn node() -> Subprocess {
+        Subprocess {
+            program: "node",
+            args: Vec::new(),
+            suffix: ".mjs",
+            typescript: false,
+            lines: NODE_LINES,
+        }
+    }
+
+    pub fn bun() -> Subprocess {
+        Subprocess {
+            program: "bun",
+            args: vec!["run".to_string()],
+            suffix: ".ts",
+            typescript: true,
+            lines: NODE_LINES,
+        }
+    }
+}
+
+impl Backend for Subprocess {
+    fn typescript(&self) -> bool {
+        self.typescript
+    }
+
+    fn lines(&self) -> &'static str {
+        self.lines
+    }
+
+    fn spawn(&self, code: String, capture: bool) -> Result<Process> {
+        let mut file = tempfile::Builder::new()
+            .prefix("jsq")
+            .suffix(self.suffix)
+            .tempfile()?;
+        file.write_all(code.as_bytes())?;
+        let module = file.into_temp_path();
+
+        let mut child = Command::new(self.program)
+            .args(&self.args)
+            .arg(&module)
+            .stdin(Stdio::piped())
+            .stdout(if capture {
+                Stdio::piped()
+            } else {
+                Stdio::inherit()
+            })
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    anyhow!("command not found: {}", self.program)
+                } else {
+                    err.into()
+                }
+            })?;
+
+        let stdin = child.stdin.take().context("opening stdin")?;
+        let stdout = child
+            .stdout
+            .take()
+            .map(|stdout| Box::new(stdout) as Box<dyn Read + Send>);
+        let stderr = child.stderr.take().context("opening stderr")?;
+        let name = module
+            .file_name()
+            .context("getting module name")?
+            .to_string_lossy()
+            .into_owned();
+        Ok(Process::new(
+            Box::new(stdin),
+            stdout,
+            Box::new(stderr),
+            name,
+            move || {
+                let status = child.wait()?;
+                drop(module);
+                Ok(status.code().unwrap_or(1))
+            },
+        ))
+    }
+}
@@ -1,16 +1,30 @@
 use std::fmt::Write as _;
-use std::io::Write as _;
-use std::process::{Command, Stdio};
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result, anyhow, bail, ensure};
 use oxc::allocator::{Allocator, TakeIn};
-use oxc::ast::ast::{Program, Statement};
+use oxc::ast::ast::{
+    ArrowFunctionExpression, Class, ExportAllDeclaration, ExportNamedDeclaration,
+    ExpressionStatement, Function, FunctionBody, ImportDeclaration, ImportExpression, Program,
+    ReturnStatement, Statement, StringLiteral, TryStatement,
+};
 use oxc::ast::{AstBuilder, ast::Expression};
-use oxc::ast_visit::VisitMut;
-use oxc::codegen::Codegen;
-use oxc::parser::Parser;
-use oxc::span::{SourceType, Span};
-use serde_json::Value;
+use oxc::ast_visit::{Visit, VisitMut};
+use oxc::codegen::{Codegen, CodegenOptions};
+use oxc::parser::{ParseOptions, Parser};
+use oxc::semantic::SemanticBuilder;
+use oxc::span::{GetSpan, GetSpanMut, SPAN, SourceType, Span};
+use oxc::syntax::scope::ScopeFlags;
+use oxc::transformer::{TransformOptions, Transformer};
+use oxc_sourcemap::SourceMap;
+use serde_json::{Map, Value};
+use url::Url;
+
+use crate::backend::Backend;
+use crate::parse::Records;
+use crate::print;
 
 #[derive(Copy, Clone)]
 pub enum Print {
@@ -19,141 +33,572 @@ pub enum Print {
     Object,
 }
 
+/// Input to be bound to `$`.
+pub enum Input {
+    /// Plain text.
+    Text(String),
+    /// A JSON string, to be parsed.
+    Json(String),
+    /// A stream of JSON records, each of which is evaluated separately.
+    Records(Records),
+}
+
 pub struct Options<'a, I> {
-    pub input: &'a str,
+    pub backend: &'a dyn Backend,
+    pub input: Input,
     pub env: I,
+    /// Values to bind as well as the environment, which take precedence over it.
+    pub named: Map<String, Value>,
     pub script: &'a str,
-    pub parse: bool,
+    /// The file SCRIPT was read from, if any.
+    pub file: Option<&'a str>,
     pub print: Print,
+    pub skip_undefined: bool,
+    /// Report whether the result is truthy, as well as printing it.
+    pub exit_status: bool,
 }
 
-pub fn eval<I: Iterator<Item = (String, String)>>(
-    options: Options<'_, I>,
-) -> Result<Option<Value>> {
-    let allocator = Allocator::new();
+/// Numbers the rest of the input, which is a stream of records.
+const RECORDS: &str = r"
+    async function* __jsq_records() {
+        let i = 0;
+        for await (const record of __jsq_input) {
+            yield [++i, record];
+        }
+    }
+";
 
-    let mut program = parse(&allocator, options.script)?;
+/// Evaluate SCRIPT, passing each result to `emit`. Returns whether the last result was truthy, when
+/// `exit_status` is set.
+pub fn eval<I, F>(mut options: Options<'_, I>, mut emit: F) -> Result<bool>
+where
+    I: Iterator<Item = (String, String)>,
+    F: FnMut(Value) -> Result<()>,
+{
+    // Results are printed after a marker unique to this run, so that they can be told apart from
+    // anything else the script prints.
+    let marker = format!("\x1e{:016x}", RandomState::new().build_hasher().finish());
+    let mut env: Map<String, Value> = options
+        .env
+        .by_ref()
+        // Ignore weird environment variable names.
+        .filter(|(k, _)| k.chars().all(|c| c.is_alphanumeric() || c == '_'))
+        .map(|(k, v)| (k, Value::String(v)))
+        .collect();
+    env.extend(std::mem::take(&mut options.named));
+    let (code, map) = generate(&options, env.keys(), &marker)?;
+    let name = options.file.unwrap_or("SCRIPT");
 
-    program.body.insert(
-        0,
-        sub_undefined(
-            &allocator,
-            if options.parse {
-                "const $ = JSON.parse(undefined);"
+    let mut process = options.backend.spawn(
+        code,
+        matches!(options.print, Print::Object) || options.exit_status,
+    )?;
+
+    let stderr = process.stderr.take().context("opening stderr")?;
+    let (module, errors_name) = (process.module.clone(), name.to_string());
+    let errors = std::thread::spawn(move || read_errors(stderr, &map, &module, &errors_name));
+
+    let stdin = process.stdin.take().context("opening stdin")?;
+    let input = options.input;
+    let writer = std::thread::spawn(move || write_input(stdin, env, input));
+
+    let mut truthy = false;
+    if let Some(stdout) = process.stdout.take() {
+        truthy = read_results(stdout, &marker, &mut emit)?;
+    }
+
+    let code = process.wait()?;
+    let thrown = errors.join().expect("joining error reader")?;
+
+    if code != 0 {
+        // The error will have been printed already, so just point at where it came from.
+        if let Some((line, column)) = thrown {
+            print::snippet(
+                &mut print::stderr(),
+                &format!("{name}:{line}:{column}"),
+                options.script.lines().nth(line - 1).unwrap_or_default(),
+                line,
+                column,
+            )?;
+        }
+        // With --exit-status, 1 means the result was falsy, so errors are distinguished by 2.
+        std::process::exit(if options.exit_status { 2 } else { code });
+    }
+
+    writer.join().expect("joining writer")?;
+    Ok(truthy)
+}
+
+/// Copy `stdout` through to our own STDOUT as it arrives, apart from results, which are emitted.
+/// Returns whether the last result was truthy, if that was reported.
+fn read_results(
+    stdout: impl Read,
+    marker: &str,
+    emit: &mut impl FnMut(Value) -> Result<()>,
+) -> Result<bool> {
+    let mut truthy = false;
+    let mut reader = BufReader::new(stdout);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let mut out = std::io::stdout().lock();
+        if let Some(i) = line
+            .windows(marker.len())
+            .position(|window| window == marker.as_bytes())
+        {
+            // Whatever the script printed without a trailing newline before the result.
+            if i > 0 {
+                out.write_all(&line[..i])?;
+                writeln!(out)?;
+            }
+            drop(out);
+            let result = std::str::from_utf8(&line[i + marker.len()..])?.trim_end();
+            // JSON can't start with !, so this is whether the result is truthy.
+            if let Some(status) = result.strip_prefix('!') {
+                truthy = status == "true";
+            } else {
+                emit_result(result, emit)?;
+            }
+        } else {
+            out.write_all(&line)?;
+            out.flush()?;
+        }
+        line.clear();
+    }
+    Ok(truthy)
+}
+
+/// Copy `stderr` through to our own STDERR as it arrives, with locations in `module` mapped back to
+/// SCRIPT. Returns the first such location with a column, which is where any uncaught error was
+/// thrown from.
+fn read_errors(
+    stderr: impl Read,
+    map: &SourceMap,
+    module: &str,
+    name: &str,
+) -> Result<Option<(usize, usize)>> {
+    let table = map.generate_lookup_table();
+    // Lines and columns count from 1 in stack traces, but from 0 in source maps.
+    let lookup = |line: usize, column: Option<usize>| {
+        let line = u32::try_from(line.checked_sub(1)?).ok()?;
+        let token = match column {
+            Some(column) => {
+                map.lookup_token(&table, line, u32::try_from(column.saturating_sub(1)).ok()?)?
+            }
+            None => *table.get(line as usize)?.first()?,
+        };
+        token.get_source_id()?;
+        Some((
+            token.get_src_line() as usize + 1,
+            token.get_src_col() as usize + 1,
+        ))
+    };
+
+    let mut thrown = None;
+    let mut reader = BufReader::new(stderr);
+    let mut buf = Vec::new();
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        let line = String::from_utf8_lossy(&buf);
+        let mut out = String::new();
+        let mut rest = line.as_ref();
+        while let Some(i) = rest.find(module) {
+            let (before, after) = (&rest[..i], &rest[i + module.len()..]);
+            // Anything leading up to the module name should be its directory.
+            let start = before
+                .rfind(|c: char| c.is_whitespace() || c == '(' || c == '@')
+                .map_or(0, |j| j + 1);
+            if let Some((line, column, tail)) = position(after)
+                && (start == i || before.ends_with('/'))
+                && let Some((src_line, src_column)) = lookup(line, column)
+            {
+                out.push_str(&before[..start]);
+                write!(out, "{name}:{src_line}")?;
+                if column.is_some() {
+                    write!(out, ":{src_column}")?;
+                    thrown.get_or_insert((src_line, src_column));
+                }
+                rest = tail;
             } else {
-                "const $ = undefined;"
-            },
-            string_literal(&allocator, options.input),
-        )?,
+                out.push_str(&rest[..i + module.len()]);
+                rest = after;
+            }
+        }
+        out.push_str(rest);
+        let mut err = std::io::stderr().lock();
+        err.write_all(out.as_bytes())?;
+        err.flush()?;
+        buf.clear();
+    }
+    Ok(thrown)
+}
+
+/// Parse `:LINE:COLUMN` or `:LINE` from the start of `s`, returning the rest of `s` as well.
+fn position(s: &str) -> Option<(usize, Option<usize>, &str)> {
+    fn number(s: &str) -> Option<(usize, &str)> {
+        let s = s.strip_prefix(':')?;
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        Some((s[..end].parse().ok()?, &s[end..]))
+    }
+
+    let (line, s) = number(s)?;
+    Some(match number(s) {
+        Some((column, s)) => (line, Some(column), s),
+        None => (line, None, s),
+    })
+}
+
+/// Write the environment, and then the input, to the script's STDIN, one JSON value per line.
+fn write_input(mut w: impl Write, env: Map<String, Value>, input: Input) -> Result<()> {
+    let lines: Records = match input {
+        Input::Text(text) => Box::new(std::iter::once(Ok(Value::String(text).to_string()))),
+        Input::Json(json) => Box::new(std::iter::once(Ok(json))),
+        Input::Records(records) => records,
+    };
+    for line in std::iter::once(Ok(Value::Object(env).to_string())).chain(lines) {
+        let mut line = line?;
+        line.push('\n');
+        match w.write_all(line.as_bytes()) {
+            // The script has stopped reading, so it must have exited early.
+            Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            res => res.context("writing to stdin")?,
+        }
+    }
+    Ok(())
+}
+
+fn generate<'e, I: Iterator<Item = (String, String)>>(
+    options: &Options<'_, I>,
+    env: impl Iterator<Item = &'e String>,
+    marker: &str,
+) -> Result<(String, SourceMap)> {
+    let allocator = Allocator::new();
+
+    // Scripts can return early, in which case they're wrapped in a function.
+    let mut program = parse_with_options(
+        &allocator,
+        options.script,
+        ParseOptions {
+            allow_return_outside_function: true,
+            ..ParseOptions::default()
+        },
+    )?;
+
+    let ast = AstBuilder::new(&allocator);
+    // A script which is nothing but a string is parsed as a directive, like "use strict".
+    if program.body.is_empty()
+        && let Some(directive) = program.directives.pop()
+    {
+        program.body.push(ast.statement_expression(
+            directive.span,
+            Expression::StringLiteral(ast.alloc(directive.expression)),
+        ));
+    }
+
+    let mut returns = FindReturn(false);
+    returns.visit_program(&program);
+    if returns.0 {
+        wrap_in_function(&allocator, &mut program.body)?;
+    }
+
+    if !matches!(options.print, Print::None) || options.exit_status {
+        print_result(&allocator, &mut program, options, marker)?;
+    }
+
+    // Everything is read from STDIN: first the environment, then the input.
+    let mut prelude = String::from(options.backend.lines());
+    prelude.push_str(
+        "const __jsq_input = __jsq_lines(); const __jsq_env = (await __jsq_input.next()).value;",
     );
+    for k in env {
+        write!(&mut prelude, "const ${k} = __jsq_env[{}];", js_string(k))?;
+    }
+    let mut prelude = parse_statements(&allocator, AstBuilder::new(&allocator).str(&prelude))?;
 
-    for (k, v) in options.env {
-        // Ignore weird environment variable names.
-        if k.chars().all(|c| c.is_alphanumeric() || c == '_') {
-            program.body.insert(
-                0,
-                sub_undefined(
-                    &allocator,
-                    AstBuilder::new(&allocator).str(&format!("const ${k} = undefined;")),
-                    string_literal(&allocator, AstBuilder::new(&allocator).str(&v)),
-                )?,
-            );
-        }
-    }
-
-    if !matches!(options.print, Print::None) {
-        let statement = program.body.pop().expect("program is not empty");
-        if let Statement::ExpressionStatement(mut expression_statement) = statement {
-            program.body.push(sub_undefined(
-                &allocator,
-                match options.print {
-                    Print::String => {
-                        r#"
-                            ((res) => {
-                                if (typeof res === "string" && res.endsWith("\n")) {
-                                    res = res.slice(0, -1);
-                                }
-                                console.log(res);
-                            })(undefined);
-                        "#
-                    }
-                    Print::Object => "console.log(JSON.stringify(undefined));",
-                    Print::None => unreachable!(),
-                },
-                expression_statement.expression.take_in(&allocator),
-            )?);
-        } else {
-            // Final statement isn't an expression statement so result is undefined.
-            program.body.push(statement);
-            program
-                .body
-                .push(parse_statment(&allocator, "console.log(undefined);")?);
+    if let Input::Records(_) = options.input {
+        // Imports have to stay at the top level, everything else is evaluated once per record.
+        let (imports, body): (Vec<_>, Vec<_>) = program
+            .body
+            .drain(..)
+            .partition(|statement| matches!(statement, Statement::ImportDeclaration(_)));
+        let mut statement = parse_statment(
+            &allocator,
+            "for await (const [$i, $] of __jsq_records()) {}",
+        )?;
+        if let Statement::ForOfStatement(for_of) = &mut statement
+            && let Statement::BlockStatement(block) = &mut for_of.body
+        {
+            block.body.extend(body);
         }
+        program.body.extend(imports);
+        program.body.extend(prelude);
+        program.body.extend(parse_statements(&allocator, RECORDS)?);
+        program.body.push(statement);
+    } else {
+        prelude.push(parse_statment(
+            &allocator,
+            "const $ = (await __jsq_input.next()).value;",
+        )?);
+        program.body.splice(0..0, prelude);
     }
 
-    let code = Codegen::new().build(&program).code;
+    // The module is run from a temporary file rather than the working directory, so relative
+    // imports need resolving explicitly.
+    ResolveImports {
+        allocator: &allocator,
+        base: Url::from_directory_path(std::env::current_dir()?)
+            .map_err(|()| anyhow!("getting working directory URL"))?,
+    }
+    .visit_program(&mut program);
+
+    if !options.backend.typescript() {
+        strip_types(&allocator, &mut program)?;
+    }
 
-    let mut child = Command::new("deno")
-        .arg("run")
-        .arg("--no-config")
-        .arg("--allow-all")
-        .arg("-")
-        .stdin(Stdio::piped())
-        .stdout(match options.print {
-            Print::Object => Stdio::piped(),
-            _ => Stdio::inherit(),
+    let res = Codegen::new()
+        .with_options(CodegenOptions {
+            source_map_path: Some(PathBuf::from(options.file.unwrap_or("SCRIPT"))),
+            ..CodegenOptions::default()
         })
-        .spawn()
-        .map_err(|err| {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                anyhow!("command not found: deno")
+        .build(&program);
+    Ok((res.code, res.map.context("generating source map")?))
+}
+
+/// Transpile TypeScript to JavaScript.
+#[cfg(feature = "quickjs")]
+pub fn transpile(s: &str) -> Result<String> {
+    let allocator = Allocator::new();
+    let mut program = parse(&allocator, s)?;
+    strip_types(&allocator, &mut program)?;
+    Ok(Codegen::new().build(&program).code)
+}
+
+fn strip_types<'a>(allocator: &'a Allocator, program: &mut Program<'a>) -> Result<()> {
+    let scoping = SemanticBuilder::new()
+        .build(program)
+        .semantic
+        .into_scoping();
+    let res = Transformer::new(allocator, Path::new("jsq.ts"), &TransformOptions::default())
+        .build_with_scoping(scoping, program);
+    if !res.errors.is_empty() {
+        let mut msg = String::from("transpiling script:");
+        for err in res.errors {
+            msg.push_str("\n  - ");
+            write!(&mut msg, "{err}")?;
+        }
+        bail!(msg);
+    }
+    Ok(())
+}
+
+/// Replace the final statement of `program` with one which prints its result. Objects are printed
+/// as JSON after `marker`, as is whether the result is truthy, for --exit-status.
+fn print_result<'a, I>(
+    allocator: &'a Allocator,
+    program: &mut Program<'a>,
+    options: &Options<'_, I>,
+    marker: &str,
+) -> Result<()> {
+    let object = format!("console.log({} + JSON.stringify(res));", js_string(marker));
+    let code = match options.print {
+        Print::String => {
+            r#"
+                if (typeof res === "string" && res.endsWith("\n")) {
+                    res = res.slice(0, -1);
+                }
+                console.log(res);
+            "#
+        }
+        Print::Object => &object,
+        Print::None => "",
+    };
+    let status = if options.exit_status {
+        format!(
+            r#"console.log({} + (res ? "!true" : "!false"));"#,
+            js_string(marker),
+        )
+    } else {
+        String::new()
+    };
+    // Note that the check for undefined uses typeof, since the first undefined in the template
+    // is substituted for the result. Promises (or any other thenables) are awaited first. The
+    // status comes before the check, so that a skipped result still counts as the last one.
+    let template = AstBuilder::new(allocator).str(&format!(
+        "await (async (res) => {{ res = await res; {status} {} {code} }})(undefined);",
+        if options.skip_undefined {
+            r#"if (typeof res === "undefined") return;"#
+        } else {
+            ""
+        },
+    ));
+
+    // Errors thrown by the final expression, like a call to a function which doesn't exist, can
+    // be reported at the start of the statement it ends up in, so that's mapped to the original.
+    let span = program.body.last().map_or(SPAN, GetSpan::span);
+    let result = complete(allocator, &mut program.body)?;
+    let mut statement = sub_undefined(allocator, template, result)?;
+    *statement.span_mut() = span;
+    if let Statement::ExpressionStatement(it) = &mut statement {
+        *it.expression.span_mut() = span;
+    }
+    program.body.push(statement);
+    Ok(())
+}
+
+/// Remove the final statement from `body`, returning an expression for its result, which is valid
+/// after the rest of `body`.
+fn complete<'a>(
+    allocator: &'a Allocator,
+    body: &mut oxc::allocator::Vec<'a, Statement<'a>>,
+) -> Result<Expression<'a>> {
+    let ast = AstBuilder::new(allocator);
+    match body.pop() {
+        Some(Statement::ExpressionStatement(mut expression_statement)) => {
+            Ok(expression_statement.expression.take_in(allocator))
+        }
+        Some(mut statement) => {
+            // Otherwise, the result is the completion value of the final statement, as it would
+            // be for eval.
+            body.push(parse_statment(allocator, "let __jsq_res;")?);
+            let mut completion = Completion {
+                allocator,
+                error: None,
+            };
+            if let Statement::VariableDeclaration(declaration) = &mut statement
+                && let Some(declarator) = declaration.declarations.last_mut()
+                && let Some(init) = &mut declarator.init
+            {
+                completion.assign(init);
             } else {
-                err.into()
+                completion.visit_statement(&mut statement);
             }
-        })?;
-
-    // Write code to child's STDIN from another thread to avoid potential deadlock. From
-    // https://doc.rust-lang.org/std/process/struct.Stdio.html#method.piped:
-    //
-    // > Writing more than a pipe buffer’s worth of input to stdin without also reading stdout and
-    // > stderr at the same time may cause a deadlock. This is an issue when running any program
-    // > that doesn’t guarantee that it reads its entire stdin before writing more than a pipe
-    // > buffer’s worth of output. The size of a pipe buffer varies on different targets.
-    let mut cmdin = child.stdin.take().context("opening stdin")?;
-    std::thread::spawn(move || {
-        cmdin.write_all(code.as_bytes()).expect("writing to stdin");
-    });
-
-    let output = child.wait_with_output()?;
-
-    if !output.status.success() {
-        // Deno will have printed the error already so exit silently.
-        std::process::exit(output.status.code().unwrap_or(1));
-    }
-
-    match options.print {
-        Print::None | Print::String => Ok(None),
-        Print::Object => {
-            let output = String::from_utf8(output.stdout)?;
-            let mut output = output.trim_end();
-            if let Some((left, right)) = output.rsplit_once('\n') {
-                println!("{left}");
-                output = right;
+            if let Some(err) = completion.error {
+                return Err(err);
             }
+            body.push(statement);
+            Ok(ast.expression_identifier(SPAN, "__jsq_res"))
+        }
+        None => Ok(ast.expression_identifier(SPAN, "undefined")),
+    }
+}
+
+/// Move everything but imports and exports in to the body of an async function, which returns the
+/// result, so that `return` can be used to finish early.
+fn wrap_in_function<'a>(
+    allocator: &'a Allocator,
+    body: &mut oxc::allocator::Vec<'a, Statement<'a>>,
+) -> Result<()> {
+    struct FillBody<'a> {
+        body: Option<oxc::allocator::Vec<'a, Statement<'a>>>,
+    }
+
+    impl<'a> VisitMut<'a> for FillBody<'a> {
+        fn visit_function_body(&mut self, it: &mut FunctionBody<'a>) {
+            if let Some(body) = self.body.take() {
+                it.statements.extend(body);
+            }
+        }
+    }
+
+    let (module, mut function): (Vec<_>, Vec<_>) =
+        body.drain(..).partition(Statement::is_module_declaration);
+    let mut function = oxc::allocator::Vec::from_iter_in(function.drain(..), allocator);
+    let span = function.last().map_or(SPAN, GetSpan::span);
+    let result = complete(allocator, &mut function)?;
+    function.push(AstBuilder::new(allocator).statement_return(span, Some(result)));
 
-            // undefined is a valid output of JSON.stringify
-            if output == "undefined" {
-                println!("undefined");
-                return Ok(None);
+    let mut statement = parse_statment(allocator, "await (async () => {})();")?;
+    oxc::ast_visit::walk_mut::walk_statement(
+        &mut FillBody {
+            body: Some(function),
+        },
+        &mut statement,
+    );
+    body.extend(module);
+    body.push(statement);
+    Ok(())
+}
+
+/// Finds any `return` outside of a function.
+struct FindReturn(bool);
+
+impl<'a> Visit<'a> for FindReturn {
+    fn visit_return_statement(&mut self, _: &ReturnStatement<'a>) {
+        self.0 = true;
+    }
+
+    fn visit_function(&mut self, _: &Function<'a>, _: ScopeFlags) {}
+
+    fn visit_arrow_function_expression(&mut self, _: &ArrowFunctionExpression<'a>) {}
+
+    fn visit_class(&mut self, _: &Class<'a>) {}
+}
+
+/// Assigns the value of every expression statement to `__jsq_res`, so that it ends up with the
+/// completion value of the statement visited.
+struct Completion<'a> {
+    allocator: &'a Allocator,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a> Completion<'a> {
+    fn assign(&mut self, expression: &mut Expression<'a>) {
+        let assignment = sub_undefined(
+            self.allocator,
+            "__jsq_res = undefined;",
+            expression.take_in(self.allocator),
+        );
+        match assignment {
+            Ok(Statement::ExpressionStatement(mut statement)) => {
+                *expression = statement.expression.take_in(self.allocator);
             }
+            Ok(_) => unreachable!(),
+            Err(err) => self.error = Some(err),
+        }
+    }
+}
 
-            Ok(Some(output.parse()?))
+impl<'a> VisitMut<'a> for Completion<'a> {
+    fn visit_expression_statement(&mut self, it: &mut ExpressionStatement<'a>) {
+        self.assign(&mut it.expression);
+    }
+
+    // The completion value of a try statement never comes from its finally block.
+    fn visit_try_statement(&mut self, it: &mut TryStatement<'a>) {
+        self.visit_block_statement(&mut it.block);
+        if let Some(handler) = &mut it.handler {
+            self.visit_block_statement(&mut handler.body);
         }
     }
+
+    // Anything nested in a function, class, or expression is evaluated separately.
+    fn visit_function(&mut self, _: &mut Function<'a>, _: ScopeFlags) {}
+
+    fn visit_class(&mut self, _: &mut Class<'a>) {}
+
+    fn visit_expression(&mut self, _: &mut Expression<'a>) {}
+}
+
+fn emit_result(result: &str, emit: &mut impl FnMut(Value) -> Result<()>) -> Result<()> {
+    // undefined is a valid output of JSON.stringify
+    if result == "undefined" {
+        println!("undefined");
+        Ok(())
+    } else {
+        emit(result.parse()?)
+    }
 }
 
 fn parse<'a>(allocator: &'a Allocator, s: &'a str) -> Result<Program<'a>> {
-    let res = Parser::new(allocator, s, SourceType::ts()).parse();
+    parse_with_options(allocator, s, ParseOptions::default())
+}
+
+fn parse_with_options<'a>(
+    allocator: &'a Allocator,
+    s: &'a str,
+    options: ParseOptions,
+) -> Result<Program<'a>> {
+    let res = Parser::new(allocator, s, SourceType::ts())
+        .with_options(options)
+        .parse();
     if !res.errors.is_empty() {
         let mut msg = String::from("parsing script:");
         for err in res.errors {
@@ -165,10 +610,28 @@ fn parse<'a>(allocator: &'a Allocator, s: &'a str) -> Result<Program<'a>> {
     Ok(res.program)
 }
 
-fn parse_statment<'a>(allocator: &'a Allocator, s: &'a str) -> Result<Statement<'a>> {
+/// Parse generated code, which has no place in the source map, so its spans are cleared.
+fn parse_statements<'a>(
+    allocator: &'a Allocator,
+    s: &'a str,
+) -> Result<oxc::allocator::Vec<'a, Statement<'a>>> {
+    struct ClearSpans;
+
+    impl VisitMut<'_> for ClearSpans {
+        fn visit_span(&mut self, it: &mut Span) {
+            *it = SPAN;
+        }
+    }
+
     let mut program = parse(allocator, s)?;
-    ensure!(program.body.len() == 1);
-    Ok(program.body.swap_remove(0))
+    ClearSpans.visit_program(&mut program);
+    Ok(program.body)
+}
+
+fn parse_statment<'a>(allocator: &'a Allocator, s: &'a str) -> Result<Statement<'a>> {
+    let mut statements = parse_statements(allocator, s)?;
+    ensure!(statements.len() == 1);
+    Ok(statements.swap_remove(0))
 }
 
 fn sub_undefined<'a>(
@@ -202,10 +665,47 @@ fn sub_undefined<'a>(
     Ok(statement)
 }
 
-fn string_literal<'a>(allocator: &'a Allocator, s: &'a str) -> Expression<'a> {
-    Expression::StringLiteral(AstBuilder::new(allocator).alloc_string_literal(
-        Span::new(0, 0),
-        s,
-        None,
-    ))
+/// `s` as a JavaScript string literal.
+fn js_string(s: &str) -> String {
+    Value::String(s.to_string()).to_string()
+}
+
+struct ResolveImports<'a> {
+    allocator: &'a Allocator,
+    base: Url,
+}
+
+impl<'a> ResolveImports<'a> {
+    fn resolve(&self, source: &mut StringLiteral<'a>) {
+        if (source.value.starts_with("./") || source.value.starts_with("../"))
+            && let Ok(url) = self.base.join(&source.value)
+        {
+            source.value = AstBuilder::new(self.allocator).atom(url.as_str());
+            source.raw = None;
+        }
+    }
+}
+
+impl<'a> VisitMut<'a> for ResolveImports<'a> {
+    fn visit_import_declaration(&mut self, it: &mut ImportDeclaration<'a>) {
+        self.resolve(&mut it.source);
+    }
+
+    fn visit_export_all_declaration(&mut self, it: &mut ExportAllDeclaration<'a>) {
+        self.resolve(&mut it.source);
+    }
+
+    fn visit_export_named_declaration(&mut self, it: &mut ExportNamedDeclaration<'a>) {
+        if let Some(source) = &mut it.source {
+            self.resolve(source);
+        }
+        oxc::ast_visit::walk_mut::walk_export_named_declaration(self, it);
+    }
+
+    fn visit_import_expression(&mut self, it: &mut ImportExpression<'a>) {
+        if let Expression::StringLiteral(source) = &mut it.source {
+            self.resolve(source);
+        }
+        oxc::ast_visit::walk_mut::walk_import_expression(self, it);
+    }
 }
@@ -0,0 +1,445 @@
+use std::io::Write;
+use std::ops::Range;
+
+use anyhow::{Context, Result, bail};
+use saphyr_parser::{Event, Parser, ScalarStyle};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use termcolor::NoColor;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, TableLike};
+
+use crate::parse::{self, Format};
+use crate::print;
+
+/// The key `toml` uses to represent datetimes as JSON.
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+
+/// Serialize `value` in `format` to replace `original`, preserving the comments and formatting of
+/// anything which hasn't changed where the format allows.
+pub fn rewrite(
+    original: &[u8],
+    format: Format,
+    options: &parse::Options,
+    value: &Value,
+) -> Result<Vec<u8>> {
+    let mut w = Vec::new();
+    match format {
+        Format::MessagePack => print::msgpack(&mut w, value)?,
+        Format::Cbor => print::cbor(&mut w, value)?,
+        _ => {
+            let original =
+                std::str::from_utf8(original).with_context(|| format!("parsing {format}"))?;
+            return Ok(rewrite_text(original, format, options, value)?.into_bytes());
+        }
+    }
+    Ok(w)
+}
+
+fn rewrite_text(
+    original: &str,
+    format: Format,
+    options: &parse::Options,
+    value: &Value,
+) -> Result<String> {
+    match format {
+        Format::Json => reprint(|w| print::json(w, value)),
+        Format::Yaml => match yaml(original, options.multi_doc, value)? {
+            Some(s) => Ok(s),
+            None if options.multi_doc => reprint(|w| print::yaml_docs(w, value)),
+            None => reprint(|w| print::yaml(w, value)),
+        },
+        Format::Toml => toml(original, value),
+        Format::Json5 => reprint(|w| print::json5(w, value)),
+        Format::Csv | Format::Tsv => reprint(|w| print::csv(w, value, &options.dialect(format))),
+        Format::Xml => reprint(|w| {
+            // Keep the XML declaration, if there is one.
+            if original.starts_with("<?xml")
+                && let Some(end) = original.find("?>")
+            {
+                writeln!(w, "{}", &original[..end + 2])?;
+            }
+            print::xml(w, value)
+        }),
+        Format::Jsonl => {
+            let Value::Array(values) = value else {
+                bail!("JSON Lines must be an array, got {value}");
+            };
+            let mut s = String::new();
+            for value in values {
+                s.push_str(&value.to_string());
+                s.push('\n');
+            }
+            Ok(s)
+        }
+        Format::MessagePack | Format::Cbor => unreachable!(),
+    }
+}
+
+/// Whether `a` and `b` are equal as JavaScript sees them, so that a value which has only been
+/// through JavaScript and back, like 1.0 becoming 1, or an integer beyond 2^53 being rounded,
+/// counts as unchanged.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len() && a.iter().all(|(k, a)| b.get(k).is_some_and(|b| same(a, b)))
+        }
+        _ => a == b,
+    }
+}
+
+fn reprint(f: impl FnOnce(&mut NoColor<Vec<u8>>) -> Result<()>) -> Result<String> {
+    let mut w = NoColor::new(Vec::new());
+    f(&mut w)?;
+    Ok(String::from_utf8(w.into_inner())?)
+}
+
+/// Merge `value` in to the TOML document `original`. Items which compare equal are left alone, so
+/// they keep their comments and formatting, as does everything around the items which change.
+pub fn toml(original: &str, value: &Value) -> Result<String> {
+    let mut doc: DocumentMut = original.parse().context("parsing TOML")?;
+    let Value::Object(obj) = value else {
+        bail!("a TOML document must be an object, got {value}");
+    };
+    merge_toml_table(doc.as_table_mut(), obj)?;
+    Ok(doc.to_string())
+}
+
+fn merge_toml_table(table: &mut dyn TableLike, obj: &Map<String, Value>) -> Result<()> {
+    let stale: Vec<String> = table
+        .iter()
+        .map(|(k, _)| k.to_string())
+        .filter(|k| !obj.contains_key(k))
+        .collect();
+    for k in stale {
+        table.remove(&k);
+    }
+    for (k, v) in obj {
+        if let Some(item) = table.get_mut(k) {
+            merge_toml_item(item, v)?;
+        } else {
+            table.insert(k, toml_item(v)?);
+        }
+    }
+    Ok(())
+}
+
+fn merge_toml_item(item: &mut Item, value: &Value) -> Result<()> {
+    match (&mut *item, value) {
+        (Item::Table(table), Value::Object(obj)) if toml_datetime(value).is_none() => {
+            merge_toml_table(table, obj)
+        }
+        (Item::ArrayOfTables(tables), Value::Array(arr)) if arr.iter().all(Value::is_object) => {
+            while tables.len() > arr.len() {
+                tables.remove(tables.len() - 1);
+            }
+            for (i, obj) in arr.iter().filter_map(Value::as_object).enumerate() {
+                match tables.get_mut(i) {
+                    Some(table) => merge_toml_table(table, obj)?,
+                    None => tables.push(toml_table(obj)?),
+                }
+            }
+            Ok(())
+        }
+        (Item::Value(old), _) => merge_toml_value(old, value),
+        _ => {
+            *item = toml_item(value)?;
+            Ok(())
+        }
+    }
+}
+
+fn merge_toml_value(old: &mut toml_edit::Value, value: &Value) -> Result<()> {
+    match (&mut *old, value) {
+        (toml_edit::Value::InlineTable(table), Value::Object(obj))
+            if toml_datetime(value).is_none() =>
+        {
+            merge_toml_table(table, obj)
+        }
+        (toml_edit::Value::Array(values), Value::Array(arr)) => {
+            while values.len() > arr.len() {
+                values.remove(values.len() - 1);
+            }
+            for (i, v) in arr.iter().enumerate() {
+                if let Some(old) = values.get_mut(i) {
+                    merge_toml_value(old, v)?;
+                } else {
+                    // Format new values like the last one, which also keeps any whitespace before
+                    // the closing bracket at the end.
+                    let mut new = toml_value(v)?;
+                    if let Some(last) = values.iter_mut().last() {
+                        *new.decor_mut() = last.decor().clone();
+                        last.decor_mut().set_suffix("");
+                    }
+                    values.push_formatted(new);
+                }
+            }
+            Ok(())
+        }
+        _ => {
+            if !toml_scalar(old).is_some_and(|old| same(&old, value)) {
+                let decor = old.decor().clone();
+                *old = toml_value(value)?;
+                *old.decor_mut() = decor;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The JSON representation of a scalar, as produced by `parse::toml`.
+fn toml_scalar(value: &toml_edit::Value) -> Option<Value> {
+    Some(match value {
+        toml_edit::Value::String(s) => Value::from(s.value().as_str()),
+        toml_edit::Value::Integer(i) => Value::from(*i.value()),
+        toml_edit::Value::Float(f) => Value::from(*f.value()),
+        toml_edit::Value::Boolean(b) => Value::from(*b.value()),
+        toml_edit::Value::Datetime(d) => Value::Object(Map::from_iter([(
+            TOML_DATETIME.to_string(),
+            d.value().to_string().into(),
+        )])),
+        toml_edit::Value::Array(_) | toml_edit::Value::InlineTable(_) => return None,
+    })
+}
+
+fn toml_datetime(value: &Value) -> Option<toml_edit::Datetime> {
+    match value {
+        Value::Object(obj) if obj.len() == 1 => obj.get(TOML_DATETIME)?.as_str()?.parse().ok(),
+        _ => None,
+    }
+}
+
+fn toml_item(value: &Value) -> Result<Item> {
+    match value {
+        Value::Object(obj) if toml_datetime(value).is_none() => Ok(Item::Table(toml_table(obj)?)),
+        Value::Array(arr) if !arr.is_empty() && arr.iter().all(Value::is_object) => {
+            let mut tables = ArrayOfTables::new();
+            for obj in arr.iter().filter_map(Value::as_object) {
+                tables.push(toml_table(obj)?);
+            }
+            Ok(Item::ArrayOfTables(tables))
+        }
+        _ => Ok(Item::Value(toml_value(value)?)),
+    }
+}
+
+fn toml_table(obj: &Map<String, Value>) -> Result<Table> {
+    let mut table = Table::new();
+    for (k, v) in obj {
+        table.insert(k, toml_item(v)?);
+    }
+    Ok(table)
+}
+
+fn toml_value(value: &Value) -> Result<toml_edit::Value> {
+    if let Some(datetime) = toml_datetime(value) {
+        return Ok(datetime.into());
+    }
+    match value {
+        Value::Array(arr) => arr.iter().map(toml_value).collect(),
+        Value::Object(obj) => obj
+            .iter()
+            .map(|(k, v)| Ok((k, toml_value(v)?)))
+            .collect::<Result<_>>()
+            .map(toml_edit::Value::InlineTable),
+        _ => Ok(value.serialize(toml_edit::ser::ValueSerializer::new())?),
+    }
+}
+
+/// The parts of a YAML document which can be edited in place.
+enum YamlNode {
+    Scalar {
+        span: Range<usize>,
+        style: ScalarStyle,
+        anchored: bool,
+    },
+    Sequence(Vec<YamlNode>),
+    /// Only the values are kept, since keys are never edited in place.
+    Mapping(Vec<YamlNode>),
+    Alias,
+}
+
+/// Edit the scalars in the YAML `original` which differ from `value`. Returns `None` if anything
+/// else has changed, in which case the whole document has to be printed again.
+pub fn yaml(original: &str, multi_doc: bool, value: &Value) -> Result<Option<String>> {
+    let old: Value = serde_json::from_str(&if multi_doc {
+        parse::yaml_docs(original)?
+    } else {
+        parse::yaml(original)?
+    })?;
+
+    let chars: Vec<char> = original.chars().collect();
+    let mut events = Vec::new();
+    for event in Parser::new_from_str(original) {
+        let (event, span) = event.context("parsing YAML")?;
+        let (start, mut end) = (span.start.index(), span.end.index());
+        // The spans of quoted scalars can run on past the closing quote.
+        if let Event::Scalar(_, ScalarStyle::SingleQuoted | ScalarStyle::DoubleQuoted, ..) = event {
+            end = quoted_end(&chars, start);
+        }
+        events.push((event, start..end));
+    }
+    let mut events = events.into_iter().peekable();
+    let mut docs = Vec::new();
+    while let Some((event, _)) = events.next() {
+        if let Event::DocumentStart(_) = event {
+            docs.push(yaml_node(&mut events)?);
+        }
+    }
+
+    let mut edits = Vec::new();
+    let unchanged = if multi_doc {
+        let (Value::Array(old), Value::Array(new)) = (&old, value) else {
+            return Ok(None);
+        };
+        old.len() == new.len()
+            && docs.len() == old.len()
+            && docs
+                .iter()
+                .zip(old.iter().zip(new))
+                .all(|(node, (old, new))| yaml_edits(node, old, new, &mut edits))
+    } else {
+        docs.len() == 1 && yaml_edits(&docs[0], &old, value, &mut edits)
+    };
+    if !unchanged {
+        return Ok(None);
+    }
+
+    // Spans count chars rather than bytes.
+    let offsets: Vec<usize> = original
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([original.len()])
+        .collect();
+    let mut s = original.to_string();
+    for (span, text) in edits.into_iter().rev() {
+        s.replace_range(offsets[span.start]..offsets[span.end], &text);
+    }
+    Ok(Some(s))
+}
+
+/// The end of the quoted scalar which starts at `start`.
+fn quoted_end(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if quote == '"' => i += 1,
+            '\'' if quote == '\'' && chars.get(i + 1) == Some(&'\'') => i += 1,
+            c if c == quote => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+fn yaml_node<'a>(
+    events: &mut std::iter::Peekable<impl Iterator<Item = (Event<'a>, Range<usize>)>>,
+) -> Result<YamlNode> {
+    let Some((event, span)) = events.next() else {
+        bail!("parsing YAML: unexpected end of input");
+    };
+    Ok(match event {
+        Event::Scalar(_, style, anchor, _) => YamlNode::Scalar {
+            span,
+            style,
+            anchored: anchor != 0,
+        },
+        Event::SequenceStart(..) | Event::MappingStart(..) => {
+            let mapping = matches!(event, Event::MappingStart(..));
+            let mut nodes = Vec::new();
+            while !matches!(
+                events.peek(),
+                Some((Event::SequenceEnd | Event::MappingEnd, _)) | None
+            ) {
+                if mapping {
+                    yaml_node(events)?;
+                }
+                nodes.push(yaml_node(events)?);
+            }
+            events.next();
+            if mapping {
+                YamlNode::Mapping(nodes)
+            } else {
+                YamlNode::Sequence(nodes)
+            }
+        }
+        _ => YamlNode::Alias,
+    })
+}
+
+/// Collect the edits needed to turn `old` in to `new`, returning false if they aren't just edits
+/// to scalars.
+fn yaml_edits(
+    node: &YamlNode,
+    old: &Value,
+    new: &Value,
+    edits: &mut Vec<(Range<usize>, String)>,
+) -> bool {
+    if same(old, new) {
+        return true;
+    }
+    match (node, old, new) {
+        (YamlNode::Sequence(nodes), Value::Array(old), Value::Array(new)) => {
+            nodes.len() == old.len()
+                && old.len() == new.len()
+                && nodes
+                    .iter()
+                    .zip(old.iter().zip(new))
+                    .all(|(node, (old, new))| yaml_edits(node, old, new, edits))
+        }
+        (YamlNode::Mapping(nodes), Value::Object(old), Value::Object(new)) => {
+            nodes.len() == old.len()
+                && old.keys().eq(new.keys())
+                && nodes
+                    .iter()
+                    .zip(old.values().zip(new.values()))
+                    .all(|(node, (old, new))| yaml_edits(node, old, new, edits))
+        }
+        // An implicit null has an empty span, which isn't necessarily where its value would go, so
+        // it's left for the whole document to be reprinted.
+        (
+            YamlNode::Scalar {
+                span,
+                style,
+                anchored: false,
+            },
+            _,
+            _,
+        ) if !span.is_empty() => match yaml_scalar(*style, new) {
+            Some(text) => {
+                edits.push((span.clone(), text));
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Render `value` as a single line YAML scalar, keeping to `style` where possible.
+fn yaml_scalar(style: ScalarStyle, value: &Value) -> Option<String> {
+    let text = match (style, value) {
+        (ScalarStyle::Literal | ScalarStyle::Folded, _)
+        | (_, Value::Array(_) | Value::Object(_)) => {
+            return None;
+        }
+        (ScalarStyle::SingleQuoted, Value::String(s)) => format!("'{}'", s.replace('\'', "''")),
+        (ScalarStyle::Plain, Value::String(s)) => {
+            let text = reprint(|w| print::yaml(w, value)).ok()?;
+            let text = text.trim_end_matches('\n');
+            // Plain scalars in flow collections can't contain flow indicators.
+            if text.contains(|c| "\n,[]{}".contains(c)) {
+                serde_json::to_string(s).ok()?
+            } else {
+                text.to_string()
+            }
+        }
+        _ => value.to_string(),
+    };
+    (!text.contains('\n')).then_some(text)
+}
@@ -1,14 +1,22 @@
 #![warn(clippy::pedantic)]
 
+mod backend;
 mod deno;
+mod edit;
 mod parse;
 mod print;
+#[cfg(feature = "quickjs")]
+mod quickjs;
 
-use std::io::{IsTerminal, Read};
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
 
-use anyhow::{Context, Result};
-use clap::Parser;
-use deno::{Options, Print};
+use anyhow::{Context, Result, bail, ensure};
+use clap::{Parser, ValueEnum};
+use deno::{Input, Options, Print};
+use parse::{Format, Records};
+use serde_json::{Map, Value};
+use termcolor::StandardStream;
 
 /// Read data from STDIN, manipulate it with some JavaScript, write the result to STDOUT.
 #[derive(Parser)]
@@ -23,131 +31,740 @@ use deno::{Options, Print};
 #[expect(clippy::struct_excessive_bools)]
 struct Args {
     /// Parse input as JSON.
-    #[arg(short('j'), long, conflicts_with_all(["yaml_in", "toml_in", "json5_in", "csv_in"]))]
+    #[arg(short('j'), long, group("input"))]
     json_in: bool,
 
     /// Parse input as YAML.
-    #[arg(short('y'), long, conflicts_with_all(["json_in", "toml_in", "json5_in", "csv_in"]))]
+    #[arg(short('y'), long, group("input"))]
     yaml_in: bool,
 
     /// Parse input as TOML.
-    #[arg(short('t'), long, conflicts_with_all(["json_in", "yaml_in", "json5_in", "csv_in"]))]
+    #[arg(short('t'), long, group("input"))]
     toml_in: bool,
 
     /// Parse input as JSON5.
-    #[arg(short('5'), long, conflicts_with_all(["json_in", "yaml_in", "toml_in", "csv_in"]))]
+    #[arg(short('5'), long, group("input"))]
     json5_in: bool,
 
     /// Parse input as CSV.
-    #[arg(short('c'), long, conflicts_with_all(["json_in", "yaml_in", "toml_in", "json5_in"]))]
+    #[arg(short('c'), long, group("input"))]
     csv_in: bool,
 
+    /// Parse input as TSV.
+    #[arg(long, group("input"))]
+    tsv_in: bool,
+
+    /// Parse input as JSON Lines.
+    #[arg(short('l'), long, group("input"))]
+    jsonl_in: bool,
+
+    /// Parse input as XML.
+    #[arg(short('x'), long, group("input"))]
+    xml_in: bool,
+
+    /// Parse input as MessagePack.
+    #[arg(short('m'), long, group("input"))]
+    msgpack_in: bool,
+
+    /// Parse input as CBOR.
+    #[arg(long, group("input"))]
+    cbor_in: bool,
+
+    /// Detect the input format from its contents.
+    #[arg(short('a'), long, group("input"))]
+    auto_in: bool,
+
     /// Print result as JSON.
-    #[arg(short('J'), long, conflicts_with_all(["yaml_out", "toml_out", "json5_out", "csv_out", "no_out"]))]
+    #[arg(short('J'), long, group("output"))]
     json_out: bool,
 
     /// Print result as YAML.
-    #[arg(short('Y'), long, conflicts_with_all(["json_out", "toml_out", "json5_out", "csv_out", "no_out"]))]
+    #[arg(short('Y'), long, group("output"))]
     yaml_out: bool,
 
     /// Print result as TOML.
-    #[arg(short('T'), long, conflicts_with_all(["json_out", "yaml_out", "json5_out", "csv_out", "no_out"]))]
+    #[arg(short('T'), long, group("output"), conflicts_with_all(["stream", "print_lines"]))]
     toml_out: bool,
 
     /// Print result as JSON5.
-    #[arg(short('%'), long, conflicts_with_all(["json_out", "yaml_out", "toml_out", "csv_out", "no_out"]))]
+    #[arg(short('%'), long, group("output"))]
     json5_out: bool,
 
     /// Print result as CSV.
-    #[arg(short('C'), long, conflicts_with_all(["json_out", "yaml_out", "toml_out", "json5_out", "no_out"]))]
+    #[arg(short('C'), long, group("output"))]
     csv_out: bool,
 
+    /// Print result as TSV.
+    #[arg(long, group("output"))]
+    tsv_out: bool,
+
+    /// Print result as XML.
+    #[arg(short('X'), long, group("output"), conflicts_with_all(["stream", "print_lines"]))]
+    xml_out: bool,
+
+    /// Print result as MessagePack.
+    #[arg(short('M'), long, group("output"))]
+    msgpack_out: bool,
+
+    /// Print result as CBOR.
+    #[arg(long, group("output"))]
+    cbor_out: bool,
+
     /// Don't print result.
-    #[arg(short('N'), long, conflicts_with_all(["json_out", "yaml_out", "toml_out", "json5_out", "csv_out"]))]
+    #[arg(short('N'), long, group("output"))]
     no_out: bool,
 
     /// Print object keys in sorted order.
     #[arg(short('s'), long)]
     sort: bool,
 
-    /// The JavaScript to be evaluated.
-    #[arg(default_value("$"), conflicts_with("file"))]
-    script: String,
+    /// Evaluate SCRIPT once per record of input.
+    #[arg(long)]
+    stream: bool,
+
+    /// Like --stream, but without printing results.
+    #[arg(short('n'), long, group("output"), conflicts_with("print_lines"))]
+    lines: bool,
+
+    /// Like --stream, but skip results that are undefined.
+    #[arg(short('p'), long, conflicts_with_all(["no_out", "lines"]))]
+    print_lines: bool,
+
+    /// Read and write YAML as a stream of documents, each an element of an array.
+    #[arg(short('d'), long)]
+    multi_doc: bool,
+
+    /// Separate CSV fields with CHAR rather than a comma.
+    #[arg(long, value_name("CHAR"), value_parser(ascii))]
+    delimiter: Option<u8>,
+
+    /// Quote CSV fields with CHAR rather than a double quote.
+    #[arg(long, value_name("CHAR"), value_parser(ascii))]
+    quote: Option<u8>,
+
+    /// Escape quotes in CSV fields with CHAR rather than by doubling them.
+    #[arg(long, value_name("CHAR"), value_parser(ascii))]
+    escape: Option<u8>,
+
+    /// Skip lines of CSV input starting with CHAR.
+    #[arg(long, value_name("CHAR"), value_parser(ascii))]
+    comment: Option<u8>,
+
+    /// Read and write CSV without a header row, with each row an array rather than an object.
+    #[arg(long)]
+    no_header: bool,
+
+    /// How to convert CSV cells in to JSON values: none leaves them as strings, json parses any
+    /// JSON but strings, and infer parses only booleans and numbers which survive JavaScript.
+    #[arg(long, value_name("TYPES"), value_enum, default_value("json"))]
+    csv_types: parse::CsvTypes,
+
+    /// Give CSV COLUMNs a TYPE (string, number, boolean, or json), regardless of --csv-types.
+    #[arg(
+        long,
+        value_name("COLUMN:TYPE"),
+        value_delimiter(','),
+        value_parser(column)
+    )]
+    csv_schema: Vec<(String, parse::CsvType)>,
+
+    /// Read empty CSV cells as null.
+    #[arg(long)]
+    empty_null: bool,
+
+    /// Write nested values to dotted CSV columns, like address.city, and nest them again on input.
+    #[arg(long)]
+    flatten: bool,
+
+    /// Always parse XML elements named NAME in to arrays, even if there's only one.
+    #[arg(long, value_name("NAME"), value_delimiter(','))]
+    xml_array: Vec<String>,
+
+    /// Report the format each input is parsed as on STDERR.
+    #[arg(long)]
+    report_format: bool,
+
+    /// Bind $ to an object keyed by file name rather than an array of FILEs.
+    #[arg(long)]
+    keyed: bool,
+
+    /// Bind $NAME to the string VALUE.
+    #[arg(long, num_args(2), value_names(["NAME", "VALUE"]))]
+    arg: Vec<String>,
+
+    /// Bind $NAME to VALUE parsed as JSON.
+    #[arg(long, num_args(2), value_names(["NAME", "VALUE"]))]
+    argjson: Vec<String>,
+
+    /// Bind $NAME to the contents of FILE as a string.
+    #[arg(long, num_args(2), value_names(["NAME", "FILE"]))]
+    rawfile: Vec<String>,
+
+    /// Bind $NAME to the contents of FILE, parsed according to its extension or contents.
+    #[arg(long, num_args(2), value_names(["NAME", "FILE"]))]
+    slurpfile: Vec<String>,
+
+    /// The JavaScript to be evaluated. Defaults to $.
+    script: Option<String>,
+
+    /// Read input from FILEs rather than STDIN.
+    #[arg(value_name("FILE"))]
+    files: Vec<String>,
+
+    /// Edit FILE in place, writing the result back in the format it was read as.
+    #[arg(
+        short('i'),
+        long,
+        value_name("FILE"),
+        conflicts_with_all(["files", "output", "stream", "print_lines"])
+    )]
+    in_place: Option<String>,
+
+    #[cfg_attr(
+        not(feature = "quickjs"),
+        doc = "The JavaScript runtime to evaluate SCRIPT with. Defaults to deno."
+    )]
+    #[cfg_attr(
+        feature = "quickjs",
+        doc = "The JavaScript runtime to evaluate SCRIPT with, where quickjs is embedded in jsq. \
+               Defaults to deno, or to quickjs if deno isn't installed."
+    )]
+    #[arg(long, env("JSQ_RUNTIME"), value_enum)]
+    runtime: Option<backend::Runtime>,
+
+    /// Run deno with no permissions, other than those granted by --allow-*.
+    #[arg(
+        long,
+        env("JSQ_SANDBOX"),
+        value_parser(clap::builder::BoolishValueParser::new())
+    )]
+    sandbox: bool,
+
+    /// Allow SCRIPT to read PATHs, or any file. Implies --sandbox.
+    #[arg(long, value_name("PATH"), num_args(0..=1), require_equals(true), value_delimiter(','))]
+    allow_read: Option<Vec<String>>,
+
+    /// Allow SCRIPT to write PATHs, or any file. Implies --sandbox.
+    #[arg(long, value_name("PATH"), num_args(0..=1), require_equals(true), value_delimiter(','))]
+    allow_write: Option<Vec<String>>,
+
+    /// Allow SCRIPT to connect to HOSTs, or any host. Implies --sandbox.
+    #[arg(long, value_name("HOST"), num_args(0..=1), require_equals(true), value_delimiter(','))]
+    allow_net: Option<Vec<String>>,
+
+    /// Allow SCRIPT to access environment VARs, or any variable. Implies --sandbox.
+    #[arg(long, value_name("VAR"), num_args(0..=1), require_equals(true), value_delimiter(','))]
+    allow_env: Option<Vec<String>>,
+
+    /// Allow SCRIPT to run PROGRAMs, or any program. Implies --sandbox.
+    #[arg(long, value_name("PROGRAM"), num_args(0..=1), require_equals(true), value_delimiter(','))]
+    allow_run: Option<Vec<String>>,
+
+    /// Allow SCRIPT to access system information APIs, or all of them. Implies --sandbox.
+    #[arg(long, value_name("API"), num_args(0..=1), require_equals(true), value_delimiter(','))]
+    allow_sys: Option<Vec<String>>,
+
+    /// Exit with status 0 if the result is truthy, 1 if it's falsy, or 2 if there's an error.
+    #[arg(short('e'), long)]
+    exit_status: bool,
 
     /// Read SCRIPT from FILE.
-    #[arg(short('f'), long, conflicts_with("script"))]
+    #[arg(short('f'), long)]
     file: Option<String>,
+
+    /// Treat FILEs as arguments for SCRIPT, like those after --, for scripts with a shebang line.
+    #[arg(long("args"))]
+    files_as_arguments: bool,
+
+    /// Arguments for SCRIPT, available as $args, or individually as $1, $2, etc.
+    #[arg(last(true), value_name("ARG"))]
+    arguments: Vec<String>,
 }
 
-fn try_main() -> Result<()> {
-    let args = Args::parse();
+impl Args {
+    /// Decide how to parse the input from FILE (or STDIN if `file` is `None`), given a sample of
+    /// its contents.
+    fn input_format(&self, file: Option<&str>, sample: &str) -> Result<Option<Format>> {
+        let format = if self.json_in {
+            Some(Format::Json)
+        } else if self.yaml_in {
+            Some(Format::Yaml)
+        } else if self.toml_in {
+            Some(Format::Toml)
+        } else if self.json5_in {
+            Some(Format::Json5)
+        } else if self.csv_in {
+            Some(Format::Csv)
+        } else if self.tsv_in {
+            Some(Format::Tsv)
+        } else if self.jsonl_in {
+            Some(Format::Jsonl)
+        } else if self.xml_in {
+            Some(Format::Xml)
+        } else if self.msgpack_in {
+            Some(Format::MessagePack)
+        } else if self.cbor_in {
+            Some(Format::Cbor)
+        } else if let Some(format) = file.and_then(Format::from_path) {
+            Some(format)
+        } else if self.auto_in {
+            parse::detect(sample)
+        } else {
+            None
+        };
 
-    let mut input = String::new();
+        if self.report_format {
+            print::note(
+                &mut print::stderr(),
+                &format!(
+                    "reading {} as {}",
+                    file.unwrap_or("STDIN"),
+                    format.map_or_else(|| "plain text".to_string(), |format| format.to_string()),
+                ),
+            )?;
+        }
+
+        Ok(format)
+    }
+
+    fn parse_options(&self) -> parse::Options {
+        parse::Options {
+            multi_doc: self.multi_doc,
+            csv: parse::Dialect {
+                delimiter: self.delimiter.unwrap_or(b','),
+                quote: self.quote.unwrap_or(b'"'),
+                escape: self.escape,
+                comment: self.comment,
+                header: !self.no_header,
+                types: self.csv_types,
+                schema: self.csv_schema.clone(),
+                empty_null: self.empty_null,
+                flatten: self.flatten,
+            },
+            xml_arrays: self.xml_array.clone(),
+        }
+    }
+
+    /// Whether environment variable `var` is sent to SCRIPT over STDIN, which when sandboxed
+    /// requires --allow-env, the same as reading it from Deno.
+    fn env_allowed(&self, var: &str) -> bool {
+        !self.sandboxed()
+            || self
+                .allow_env
+                .as_ref()
+                .is_some_and(|vars| vars.is_empty() || vars.iter().any(|v| v == var))
+    }
+
+    fn print(&self) -> Print {
+        if self.no_out || self.lines {
+            Print::None
+        } else if self.in_place.is_some()
+            || self.json_out
+            || self.yaml_out
+            || self.toml_out
+            || self.json5_out
+            || self.csv_out
+            || self.tsv_out
+            || self.xml_out
+            || self.msgpack_out
+            || self.cbor_out
+        {
+            Print::Object
+        } else {
+            Print::String
+        }
+    }
+
+    /// The values bound by --arg, --argjson, --rawfile, and --slurpfile, as well as the arguments
+    /// for SCRIPT, by name.
+    fn named(&self) -> Result<Map<String, Value>> {
+        let mut named = Map::new();
+        named.insert(
+            "args".to_string(),
+            self.arguments.iter().cloned().map(Value::String).collect(),
+        );
+        for (i, arg) in self.arguments.iter().enumerate() {
+            named.insert((i + 1).to_string(), Value::String(arg.clone()));
+        }
+        for pair in self.arg.chunks(2) {
+            named.insert(name(&pair[0])?, Value::String(pair[1].clone()));
+        }
+        for pair in self.argjson.chunks(2) {
+            named.insert(
+                name(&pair[0])?,
+                serde_json::from_str(&pair[1])
+                    .with_context(|| format!("parsing --argjson {}", pair[0]))?,
+            );
+        }
+        for pair in self.rawfile.chunks(2) {
+            named.insert(
+                name(&pair[0])?,
+                Value::String(
+                    std::fs::read_to_string(&pair[1])
+                        .with_context(|| format!("reading {}", pair[1]))?,
+                ),
+            );
+        }
+        for pair in self.slurpfile.chunks(2) {
+            named.insert(
+                name(&pair[0])?,
+                serde_json::from_str(&self.slurp(&pair[1])?)?,
+            );
+        }
+        Ok(named)
+    }
+
+    /// Read and parse FILE, in the format given by its extension, or else detected from its
+    /// contents.
+    fn slurp(&self, file: &str) -> Result<String> {
+        let input = std::fs::read(file).with_context(|| format!("reading {file}"))?;
+        Format::from_path(file)
+            .or_else(|| parse::detect(&String::from_utf8_lossy(&input)))
+            .with_context(|| format!("can't tell what format {file} is in"))?
+            .parse(&input, &self.parse_options())
+            .map_err(|err| parse::in_file(err, file))
+    }
+
+    /// The --allow-* flags, by permission name.
+    fn allow(&self) -> [(&str, &Option<Vec<String>>); 6] {
+        [
+            ("read", &self.allow_read),
+            ("write", &self.allow_write),
+            ("net", &self.allow_net),
+            ("env", &self.allow_env),
+            ("run", &self.allow_run),
+            ("sys", &self.allow_sys),
+        ]
+    }
+
+    fn sandboxed(&self) -> bool {
+        self.sandbox || self.allow().iter().any(|(_, values)| values.is_some())
+    }
+
+    /// The Deno permission flags to run SCRIPT with, or `None` if it isn't sandboxed.
+    fn permissions(&self) -> Option<Vec<String>> {
+        if !self.sandboxed() {
+            return None;
+        }
+        Some(
+            self.allow()
+                .into_iter()
+                .filter_map(|(name, values)| {
+                    values.as_ref().map(|values| {
+                        if values.is_empty() {
+                            format!("--allow-{name}")
+                        } else {
+                            format!("--allow-{name}={}", values.join(","))
+                        }
+                    })
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Parse a single ASCII character, or \t for a tab.
+fn ascii(s: &str) -> Result<u8> {
+    match s.as_bytes() {
+        br"\t" => Ok(b'\t'),
+        &[c] if c.is_ascii() => Ok(c),
+        _ => bail!("expected a single ASCII character"),
+    }
+}
+
+/// Parse a COLUMN:TYPE pair for --csv-schema.
+fn column(s: &str) -> Result<(String, parse::CsvType)> {
+    let (column, typ) = s.rsplit_once(':').context("expected COLUMN:TYPE")?;
+    let typ = parse::CsvType::from_str(typ, false)
+        .map_err(|_| anyhow::anyhow!("expected string, number, boolean, or json, got {typ}"))?;
+    Ok((column.to_string(), typ))
+}
+
+/// Check that `name` can be bound as $NAME.
+fn name(name: &str) -> Result<String> {
+    ensure!(
+        !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_'),
+        "invalid name: {name}",
+    );
+    Ok(name.to_string())
+}
+
+fn read_stdin() -> Result<Vec<u8>> {
+    let mut input = Vec::new();
 
     let mut stdin = std::io::stdin();
     if !stdin.is_terminal() {
-        stdin.read_to_string(&mut input)?;
+        stdin.read_to_end(&mut input)?;
     }
 
-    if args.json_in {
-        input = parse::json(&input)?;
-    } else if args.yaml_in {
-        input = parse::yaml(&input)?;
-    } else if args.toml_in {
-        input = parse::toml(&input)?;
-    } else if args.json5_in {
-        input = parse::json5(&input)?;
-    } else if args.csv_in {
-        input = parse::csv(&input)?;
+    Ok(input)
+}
+
+/// Read the whole of FILE (or STDIN if `file` is `None`) and parse it. Returns the input and
+/// whether or not it was parsed.
+fn read_input(args: &Args, file: Option<&str>) -> Result<(String, bool)> {
+    let input = match file {
+        Some(file) => std::fs::read(file).with_context(|| format!("reading {file}"))?,
+        None => read_stdin()?,
+    };
+    match args.input_format(file, &String::from_utf8_lossy(&input))? {
+        Some(format) => {
+            let input = match file {
+                Some(file) => format
+                    .parse(&input, &args.parse_options())
+                    .map_err(|err| parse::in_file(err, file))?,
+                None => format.parse(&input, &args.parse_options())?,
+            };
+            Ok((input, true))
+        }
+        None => Ok((
+            String::from_utf8(input)
+                .with_context(|| format!("reading {}", file.unwrap_or("STDIN")))?,
+            false,
+        )),
     }
+}
 
-    let script = if let Some(f) = args.file {
-        std::fs::read_to_string(f)?
+/// Read records from FILE (or STDIN if `file` is `None`) one at a time.
+fn read_records(args: &Args, file: Option<&str>) -> Result<Records> {
+    // A generous buffer, since the start of the input is used to detect its format.
+    const CAPACITY: usize = 1 << 16;
+
+    let mut reader: Box<dyn BufRead + Send> = match file {
+        Some(file) => Box::new(BufReader::with_capacity(
+            CAPACITY,
+            File::open(file).with_context(|| format!("reading {file}"))?,
+        )),
+        None => Box::new(BufReader::with_capacity(CAPACITY, std::io::stdin())),
+    };
+
+    let (sample, truncated) = if args.auto_in {
+        // Ignore any incomplete line at the end of the buffer.
+        let buf = reader.fill_buf()?;
+        let end = buf
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(buf.len(), |i| i + 1);
+        (
+            String::from_utf8_lossy(&buf[..end]).into_owned(),
+            buf.len() == CAPACITY,
+        )
     } else {
-        args.script
+        (String::new(), false)
     };
 
-    let print = if args.no_out {
-        Print::None
-    } else if args.json_out || args.yaml_out || args.toml_out || args.json5_out || args.csv_out {
-        Print::Object
+    let format = args.input_format(file, &sample)?;
+    if format.is_none() && truncated {
+        // A large document might only fail to parse because the sample stops part way through it.
+        print::note(
+            &mut print::stderr(),
+            &format!(
+                "reading {} as plain text, since its format couldn't be detected from the first \
+                 {} KiB, try --FORMAT-in",
+                file.unwrap_or("STDIN"),
+                CAPACITY >> 10,
+            ),
+        )?;
+    }
+
+    parse::records(format, reader, &args.parse_options())
+}
+
+/// Read and parse every FILE (or STDIN if there are none). Returns the input and whether or not it
+/// was parsed.
+fn read_inputs(args: &Args) -> Result<(String, bool)> {
+    Ok(match args.files.as_slice() {
+        [] => read_input(args, None)?,
+        [file] if !args.keyed => read_input(args, Some(file))?,
+        files => {
+            let mut values = Vec::new();
+            for file in files {
+                let (input, parsed) = read_input(args, Some(file))?;
+                values.push((
+                    file.clone(),
+                    if parsed {
+                        serde_json::from_str(&input)?
+                    } else {
+                        Value::String(input)
+                    },
+                ));
+            }
+            let json = if args.keyed {
+                Value::Object(values.into_iter().collect())
+            } else {
+                Value::Array(values.into_iter().map(|(_, value)| value).collect())
+            };
+            (json.to_string(), true)
+        }
+    })
+}
+
+/// Returns the exit code.
+fn try_main(mut args: Args) -> Result<i32> {
+    // With --file there's no SCRIPT, so the first positional argument is actually a FILE.
+    let script = if let Some(f) = &args.file {
+        if let Some(file) = args.script.take() {
+            args.files.insert(0, file);
+        }
+        std::fs::read_to_string(f)?
     } else {
-        Print::String
+        args.script.take().unwrap_or_else(|| "$".to_string())
+    };
+    if args.files_as_arguments {
+        args.arguments.splice(0..0, std::mem::take(&mut args.files));
+    }
+
+    // Clap checks that --in-place has no FILEs before one is moved out of SCRIPT above.
+    ensure!(
+        args.in_place.is_none() || args.files.is_empty(),
+        "--in-place can't be used with FILEs",
+    );
+
+    let in_place = match &args.in_place {
+        Some(file) => {
+            let original = std::fs::read(file).with_context(|| format!("reading {file}"))?;
+            let sample = String::from_utf8_lossy(&original);
+            let format = args.input_format(Some(file), &sample)?.with_context(|| {
+                format!("can't edit {file} in place without knowing its format, try --FORMAT-in")
+            })?;
+            Some((file.clone(), original, format))
+        }
+        None => None,
     };
 
-    let output = deno::eval(Options {
-        input: &input,
-        env: std::env::vars(),
-        script: &script,
-        parse: args.json_in || args.yaml_in || args.toml_in || args.json5_in || args.csv_in,
-        print,
-    })?;
-
-    if let Some(value) = output {
-        let value = if args.sort {
-            print::sort(&value)
+    let input = if args.stream || args.lines || args.print_lines {
+        Input::Records(if args.files.is_empty() {
+            read_records(&args, None)?
         } else {
-            value
+            let mut records = Vec::new();
+            for file in &args.files {
+                records.push(read_records(&args, Some(file))?);
+            }
+            Box::new(records.into_iter().flatten())
+        })
+    } else {
+        let (text, parsed) = match &in_place {
+            Some((file, original, format)) => (
+                format
+                    .parse(original, &args.parse_options())
+                    .map_err(|err| parse::in_file(err, file))?,
+                true,
+            ),
+            None => read_inputs(&args)?,
         };
-        if args.json_out {
-            print::json(&mut print::stdout(), &value).context("printing JSON")?;
-        } else if args.yaml_out {
-            print::yaml(&mut print::stdout(), &value).context("printing YAML")?;
-        } else if args.toml_out {
-            print::toml(&mut print::stdout(), &value).context("printing TOML")?;
-        } else if args.json5_out {
-            print::json5(&mut print::stdout(), &value).context("printing JSON5")?;
-        } else if args.csv_out {
-            print::csv(&mut print::stdout(), &value).context("printing CSV")?;
+        if parsed {
+            Input::Json(text)
+        } else {
+            Input::Text(text)
         }
+    };
+
+    let backend = backend::new(args.runtime, args.permissions())?;
+    let parse_options = args.parse_options();
+    // One writer for every result, so that the header is only written once when streaming.
+    let mut csv = if args.csv_out {
+        Some(print::Csv::new(print::stdout(), &parse_options.csv))
+    } else if args.tsv_out {
+        Some(print::Csv::new(
+            print::stdout(),
+            &parse_options.dialect(Format::Tsv),
+        ))
+    } else {
+        None
+    };
+    let mut first = true;
+    let truthy = deno::eval(
+        Options {
+            backend: backend.as_ref(),
+            input,
+            env: std::env::vars().filter(|(var, _)| args.env_allowed(var)),
+            named: args.named()?,
+            script: &script,
+            file: args.file.as_deref(),
+            print: args.print(),
+            skip_undefined: args.print_lines,
+            exit_status: args.exit_status,
+        },
+        |value| {
+            let value = if args.sort {
+                print::sort(&value)
+            } else {
+                value
+            };
+            if let Some((file, original, format)) = &in_place {
+                let s = edit::rewrite(original, *format, &parse_options, &value)?;
+                std::fs::write(file, s).with_context(|| format!("writing {file}"))?;
+            } else {
+                print_result(&args, csv.as_mut(), &value, first)?;
+            }
+            first = false;
+            Ok(())
+        },
+    )?;
+    if let Some(csv) = csv {
+        csv.finish().context(csv_context(&args))?;
     }
+    Ok(i32::from(args.exit_status && !truthy))
+}
 
+/// Prints a result to stdout in the chosen output format.
+fn print_result(
+    args: &Args,
+    csv: Option<&mut print::Csv<StandardStream>>,
+    value: &Value,
+    first: bool,
+) -> Result<()> {
+    if args.json_out {
+        print::json(&mut print::stdout(), value).context("printing JSON")?;
+    } else if args.yaml_out && args.multi_doc {
+        // When streaming, every result is a document of its own.
+        if !first {
+            println!("---");
+        }
+        print::yaml_docs(&mut print::stdout(), value).context("printing YAML")?;
+    } else if args.yaml_out {
+        print::yaml(&mut print::stdout(), value).context("printing YAML")?;
+    } else if args.toml_out {
+        print::toml(&mut print::stdout(), value).context("printing TOML")?;
+    } else if args.json5_out {
+        print::json5(&mut print::stdout(), value).context("printing JSON5")?;
+    } else if let Some(csv) = csv {
+        csv.write(value).context(csv_context(args))?;
+    } else if args.xml_out {
+        print::xml(&mut print::stdout(), value).context("printing XML")?;
+    } else if args.msgpack_out {
+        let mut stdout = std::io::stdout().lock();
+        print::msgpack(&mut stdout, value).context("printing MessagePack")?;
+        stdout.flush()?;
+    } else if args.cbor_out {
+        let mut stdout = std::io::stdout().lock();
+        print::cbor(&mut stdout, value).context("printing CBOR")?;
+        stdout.flush()?;
+    }
     Ok(())
 }
 
+fn csv_context(args: &Args) -> &'static str {
+    if args.tsv_out {
+        "printing TSV"
+    } else {
+        "printing CSV"
+    }
+}
+
 fn main() {
-    if let Err(err) = try_main() {
-        print::error(&mut print::stderr(), &err).expect("printing error");
-        std::process::exit(1);
+    let args = Args::parse();
+    let exit_status = args.exit_status;
+    match try_main(args) {
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(err) => {
+            let mut stderr = print::stderr();
+            print::error(&mut stderr, &err).expect("printing error");
+            if let Some(err) = err.downcast_ref::<parse::Error>() {
+                print::parse_error(&mut stderr, err).expect("printing error");
+            }
+            std::process::exit(if exit_status { 2 } else { 1 });
+        }
     }
 }
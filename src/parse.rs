@@ -1,53 +1,832 @@
-use anyhow::{Context, Result};
-use indexmap::IndexMap;
-use serde_json::Value;
+use std::fmt;
+use std::io::{BufRead, Read, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use clap::ValueEnum;
+use csv::StringRecord;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+/// A stream of records, each a JSON string.
+pub type Records = Box<dyn Iterator<Item = Result<String>> + Send>;
+
+/// Options which affect how input is parsed.
+#[derive(Clone, Default)]
+pub struct Options {
+    /// Parse YAML as a stream of documents, in to an array.
+    pub multi_doc: bool,
+    /// The dialect of CSV, and TSV apart from the delimiter.
+    pub csv: Dialect,
+    /// XML elements which are always parsed in to arrays, even if they only appear once.
+    pub xml_arrays: Vec<String>,
+}
+
+impl Options {
+    /// The dialect to read or write `format` in, which is tab separated for TSV.
+    pub fn dialect(&self, format: Format) -> Dialect {
+        match format {
+            Format::Tsv => Dialect {
+                delimiter: b'\t',
+                ..self.csv.clone()
+            },
+            _ => self.csv.clone(),
+        }
+    }
+}
+
+/// How CSV is delimited and quoted, for both input and output, and how its cells are typed on
+/// input.
+#[derive(Clone)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    /// Escape quotes with this rather than doubling them.
+    pub escape: Option<u8>,
+    /// Skip lines starting with this.
+    pub comment: Option<u8>,
+    /// Whether the first row names the columns. Without one, rows are arrays rather than objects.
+    pub header: bool,
+    pub types: CsvTypes,
+    /// Types for particular columns, by name, or by number counting from 1 without a header.
+    pub schema: Vec<(String, CsvType)>,
+    /// Read empty cells as null.
+    pub empty_null: bool,
+    /// Spread nested values across columns with dotted names, like `address.city` or `tags.0`.
+    pub flatten: bool,
+}
+
+/// How to convert CSV cells in to JSON values.
+#[derive(Copy, Clone, Default, ValueEnum)]
+pub enum CsvTypes {
+    None,
+    #[default]
+    Json,
+    Infer,
+}
+
+/// The type of a column of CSV.
+#[derive(Copy, Clone, ValueEnum)]
+pub enum CsvType {
+    String,
+    Number,
+    Boolean,
+    Json,
+}
+
+impl Default for Dialect {
+    fn default() -> Dialect {
+        Dialect {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            comment: None,
+            header: true,
+            types: CsvTypes::Json,
+            schema: Vec::new(),
+            empty_null: false,
+            flatten: false,
+        }
+    }
+}
+
+impl Dialect {
+    pub fn reader<R: Read>(&self, r: R) -> csv::Reader<R> {
+        csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .escape(self.escape)
+            .comment(self.comment)
+            .has_headers(self.header)
+            .from_reader(r)
+    }
+
+    pub fn writer<W: Write>(&self, w: W) -> csv::Writer<W> {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .comment(self.comment);
+        if let Some(escape) = self.escape {
+            builder.escape(escape).double_quote(false);
+        }
+        builder.from_writer(w)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Json5,
+    Csv,
+    Tsv,
+    Jsonl,
+    Xml,
+    MessagePack,
+    Cbor,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "JSON",
+            Format::Yaml => "YAML",
+            Format::Toml => "TOML",
+            Format::Json5 => "JSON5",
+            Format::Csv => "CSV",
+            Format::Tsv => "TSV",
+            Format::Jsonl => "JSON Lines",
+            Format::Xml => "XML",
+            Format::MessagePack => "MessagePack",
+            Format::Cbor => "CBOR",
+        })
+    }
+}
+
+impl Format {
+    /// Infer a format from the extension of `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Format> {
+        match path.as_ref().extension()?.to_str()? {
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "json5" => Some(Format::Json5),
+            "csv" => Some(Format::Csv),
+            "tsv" => Some(Format::Tsv),
+            "jsonl" | "ndjson" => Some(Format::Jsonl),
+            "xml" => Some(Format::Xml),
+            "msgpack" | "mpk" => Some(Format::MessagePack),
+            "cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// The flag to parse input in this format.
+    pub fn flag(self) -> &'static str {
+        match self {
+            Format::Json => "-j",
+            Format::Yaml => "-y",
+            Format::Toml => "-t",
+            Format::Json5 => "-5",
+            Format::Csv => "-c",
+            Format::Tsv => "--tsv-in",
+            Format::Jsonl => "-l",
+            Format::Xml => "-x",
+            Format::MessagePack => "-m",
+            Format::Cbor => "--cbor-in",
+        }
+    }
+
+    /// Parse `input` in to a JSON string. Apart from the binary formats, `input` must be UTF-8.
+    pub fn parse(self, input: &[u8], options: &Options) -> Result<String> {
+        let s = match self {
+            Format::MessagePack => return msgpack(input),
+            Format::Cbor => return cbor(input),
+            _ => std::str::from_utf8(input).with_context(|| format!("parsing {self}"))?,
+        };
+        let res = match self {
+            Format::Json => json(s),
+            Format::Yaml if options.multi_doc => yaml_docs(s),
+            Format::Yaml => yaml(s),
+            Format::Toml => toml(s),
+            Format::Json5 => json5(s),
+            Format::Csv | Format::Tsv => csv(s, self, &options.dialect(self)),
+            Format::Jsonl => jsonl(s),
+            Format::Xml => xml(s, &options.xml_arrays),
+            Format::MessagePack | Format::Cbor => unreachable!(),
+        };
+        res.map_err(|err| match err.downcast::<Error>() {
+            Ok(mut err) => {
+                err.hint = detect(s).filter(|&format| format != self);
+                err.into()
+            }
+            Err(err) => err,
+        })
+    }
+}
+
+/// An error parsing input, with enough detail to point at where it went wrong.
+#[derive(Debug)]
+pub struct Error {
+    format: Format,
+    message: String,
+    /// Where the input came from.
+    pub name: String,
+    /// Counting from 1.
+    pub line: usize,
+    /// Counting from 1, in characters.
+    pub column: usize,
+    /// In bytes, counting from 0.
+    pub offset: usize,
+    /// The line of input which the error is on.
+    pub text: String,
+    /// A different format which the input looks like.
+    pub hint: Option<Format>,
+}
+
+impl Error {
+    fn new(format: Format, message: impl fmt::Display, s: &str, offset: usize) -> Error {
+        let mut offset = offset.min(s.len());
+        while !s.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let start = s[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let end = s[offset..].find('\n').map_or(s.len(), |i| offset + i);
+        Error {
+            format,
+            message: message.to_string(),
+            name: "STDIN".to_string(),
+            line: s[..start].matches('\n').count() + 1,
+            column: s[start..offset].chars().count() + 1,
+            offset,
+            text: s[start..end].trim_end_matches('\r').to_string(),
+            hint: None,
+        }
+    }
+
+    /// An error at `line` and `column` (both counting from 1), where `column` counts characters,
+    /// or bytes if `bytes` is set.
+    fn at(
+        format: Format,
+        message: impl fmt::Display,
+        s: &str,
+        line: usize,
+        column: usize,
+        bytes: bool,
+    ) -> Error {
+        let start: usize = s
+            .split_inclusive('\n')
+            .take(line.saturating_sub(1))
+            .map(str::len)
+            .sum();
+        let column = column.saturating_sub(1);
+        let offset = if bytes {
+            start + column
+        } else {
+            start
+                + s[start..]
+                    .chars()
+                    .take(column)
+                    .map(char::len_utf8)
+                    .sum::<usize>()
+        };
+        Error::new(format, message, s, offset)
+    }
+
+    fn json(format: Format, err: &serde_json::Error, s: &str) -> Error {
+        Error::at(format, err, s, err.line(), err.column(), true)
+    }
+
+    fn yaml(err: &serde_yaml::Error, s: &str) -> Error {
+        let (line, column) = err
+            .location()
+            .map_or((1, 1), |location| (location.line(), location.column()));
+        Error::at(Format::Yaml, err, s, line, column, false)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parsing {}: {}", self.format, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Attribute any parse error in `err` to `file`.
+pub fn in_file(err: anyhow::Error, file: &str) -> anyhow::Error {
+    match err.downcast::<Error>() {
+        Ok(mut err) => {
+            err.name = file.to_string();
+            anyhow::Error::new(err).context(file.to_string())
+        }
+        Err(err) => err.context(file.to_string()),
+    }
+}
+
+/// Guess the format of `s` by trying each parser in turn, from the strictest to the most lenient.
+/// Only formats which produce an object or an array count, since almost anything is a valid YAML
+/// string. Returns `None` if `s` looks like plain text.
+pub fn detect(s: &str) -> Option<Format> {
+    fn structured(value: &Value) -> bool {
+        value.is_object() || value.is_array()
+    }
+
+    if let Ok(values) = serde_json::Deserializer::from_str(s)
+        .into_iter::<Value>()
+        .collect::<Result<Vec<_>, _>>()
+        && !values.is_empty()
+        && values.iter().all(structured)
+    {
+        Some(if values.len() == 1 {
+            Format::Json
+        } else {
+            Format::Jsonl
+        })
+    } else if json5::from_str::<Value>(s).is_ok_and(|value| structured(&value)) {
+        Some(Format::Json5)
+    } else if toml::from_str::<toml::Table>(s).is_ok_and(|table| !table.is_empty()) {
+        Some(Format::Toml)
+    } else if yaml_documents(s).is_ok_and(|docs| !docs.is_empty() && docs.iter().all(structured)) {
+        Some(Format::Yaml)
+    } else if s.trim_start().starts_with('<') && xml(s, &[]).is_ok() {
+        Some(Format::Xml)
+    } else if looks_like_csv(s) {
+        Some(Format::Csv)
+    } else {
+        None
+    }
+}
+
+/// At least two columns and two rows, with the same number of fields in every record, and no
+/// field starting with a space, as prose with a comma in it would.
+fn looks_like_csv(s: &str) -> bool {
+    fn fields_ok(record: &csv::StringRecord) -> bool {
+        record.iter().all(|field| !field.starts_with(' '))
+    }
+
+    let mut reader = csv::Reader::from_reader(s.as_bytes());
+    reader
+        .headers()
+        .is_ok_and(|headers| headers.len() > 1 && fields_ok(headers))
+        && {
+            let mut rows = 0;
+            reader.records().all(|record| {
+                rows += 1;
+                record.is_ok_and(|record| fields_ok(&record))
+            }) && rows > 1
+        }
+}
 
 /// Parse JSON in to a JSON string.
 pub fn json(s: &str) -> Result<String> {
     Ok(serde_json::from_str::<Value>(s)
-        .context("parsing JSON")?
+        .map_err(|err| Error::json(Format::Json, &err, s))?
         .to_string())
 }
 
 /// Parse YAML in to a JSON string.
 pub fn yaml(s: &str) -> Result<String> {
     Ok(serde_yaml::from_str::<Value>(s)
-        .context("parsing YAML")?
+        .map_err(|err| Error::yaml(&err, s))?
         .to_string())
 }
 
+/// Parse a stream of YAML documents in to a JSON array string.
+pub fn yaml_docs(s: &str) -> Result<String> {
+    Ok(Value::Array(yaml_documents(s)?).to_string())
+}
+
+fn yaml_documents(s: &str) -> Result<Vec<Value>> {
+    serde_yaml::Deserializer::from_str(s)
+        .map(|doc| Value::deserialize(doc).map_err(|err| Error::yaml(&err, s).into()))
+        .collect()
+}
+
 /// Parse TOML in to a JSON string.
 pub fn toml(s: &str) -> Result<String> {
     Ok(toml::from_str::<Value>(s)
-        .context("parsing TOML")?
+        .map_err(|err| {
+            let offset = err.span().map_or(0, |span| span.start);
+            Error::new(Format::Toml, err.message(), s, offset)
+        })?
         .to_string())
 }
 
 /// Parse JSON5 in to a JSON string.
 pub fn json5(s: &str) -> Result<String> {
     Ok(json5::from_str::<Value>(s)
-        .context("parsing JSON5")?
+        .map_err(|err| {
+            let (line, column) = err
+                .position()
+                .map_or((0, 0), |position| (position.line, position.column));
+            Error::at(Format::Json5, &err, s, line + 1, column + 1, false)
+        })?
         .to_string())
 }
 
-/// Parse CSV in to a JSON string.
-pub fn csv(s: &str) -> Result<String> {
-    let mut rows: Vec<IndexMap<&str, Value>> = Vec::new();
-    let mut reader = csv::Reader::from_reader(s.as_bytes());
-    let headers = reader.headers()?;
-    for record in csv::Reader::from_reader(s.as_bytes()).records() {
+/// Parse CSV (or TSV, according to `format`) in to a JSON string.
+pub fn csv(s: &str, format: Format, dialect: &Dialect) -> Result<String> {
+    let at = |message: &dyn fmt::Display, position: Option<&csv::Position>| {
+        let offset = position.map_or(0, csv::Position::byte);
+        Error::new(
+            format,
+            message,
+            s,
+            usize::try_from(offset).unwrap_or(usize::MAX),
+        )
+    };
+    let located = |err: csv::Error| at(&err, err.position());
+    let mut reader = dialect.reader(s.as_bytes());
+    let headers = csv_headers(&mut reader).map_err(located)?;
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(located)?;
         rows.push(
-            headers
-                .iter()
-                .zip(record?.iter().map(|s| match serde_json::from_str(s) {
-                    // Avoid parsing strings, since this will have the effect of stripping the outer
-                    // quotes...
-                    Err(_) | Ok(Value::String(_)) => Value::String(s.to_string()),
-                    // ...but it's convenient to parse anything else that looks like JSON.
-                    Ok(value) => value,
-                }))
-                .collect(),
+            csv_row(dialect, headers.as_ref(), &record)
+                .map_err(|message| at(&message, record.position()))?,
         );
     }
-    Ok(serde_json::to_string(&rows)?)
+    Ok(Value::Array(rows).to_string())
+}
+
+/// The header row, if `reader` expects one.
+fn csv_headers(reader: &mut csv::Reader<impl Read>) -> csv::Result<Option<StringRecord>> {
+    Ok(if reader.has_headers() {
+        Some(reader.headers()?.clone())
+    } else {
+        None
+    })
+}
+
+/// An object keyed by `headers`, or an array without them.
+fn csv_row(
+    dialect: &Dialect,
+    headers: Option<&StringRecord>,
+    record: &StringRecord,
+) -> Result<Value, String> {
+    let mut values = Vec::new();
+    for (i, s) in record.iter().enumerate() {
+        let column = headers.map_or_else(|| (i + 1).to_string(), |headers| headers[i].to_string());
+        // An empty nested cell is one which some other row has, but this one doesn't.
+        if dialect.flatten && headers.is_some() && s.is_empty() && column.contains('.') {
+            continue;
+        }
+        let typ = dialect
+            .schema
+            .iter()
+            .find_map(|(name, typ)| (*name == column).then_some(*typ));
+        let value = csv_cell(dialect, typ, s).map_err(|err| format!("column {column}: {err}"))?;
+        values.push((column, value));
+    }
+    Ok(if headers.is_some() && dialect.flatten {
+        unflatten(values)
+    } else if headers.is_some() {
+        Value::Object(values.into_iter().collect())
+    } else {
+        values.into_iter().map(|(_, value)| value).collect()
+    })
+}
+
+/// Nest `values` according to the dots in their names. Objects keyed by 0, 1, 2, etc. become
+/// arrays.
+fn unflatten(values: Vec<(String, Value)>) -> Value {
+    fn arrays(value: Value) -> Value {
+        match value {
+            Value::Object(obj)
+                if !obj.is_empty() && obj.keys().enumerate().all(|(i, k)| *k == i.to_string()) =>
+            {
+                obj.into_iter().map(|(_, v)| arrays(v)).collect()
+            }
+            Value::Object(obj) => obj.into_iter().map(|(k, v)| (k, arrays(v))).collect(),
+            _ => value,
+        }
+    }
+
+    let mut root = Map::new();
+    for (column, value) in values {
+        let mut obj = &mut root;
+        let mut keys = column.split('.').peekable();
+        while let Some(key) = keys.next() {
+            if keys.peek().is_none() {
+                obj.insert(key.to_string(), value);
+                break;
+            }
+            let next = obj.entry(key).or_insert_with(|| Value::Object(Map::new()));
+            // A column like `a.b` takes priority over one like `a`.
+            if !next.is_object() {
+                *next = Value::Object(Map::new());
+            }
+            obj = next.as_object_mut().expect("object");
+        }
+    }
+    arrays(Value::Object(root))
+}
+
+/// Convert a cell to `typ` if it's given, or else according to `dialect.types`.
+fn csv_cell(dialect: &Dialect, typ: Option<CsvType>, s: &str) -> Result<Value, String> {
+    let mismatch = |expected| format!("expected {expected}, got {s:?}");
+    if s.is_empty() && dialect.empty_null {
+        return Ok(Value::Null);
+    }
+    Ok(match (typ, dialect.types) {
+        (Some(CsvType::String), _) | (None, CsvTypes::None) => Value::String(s.to_string()),
+        (Some(CsvType::Number), _) => Value::Number(s.parse().map_err(|_| mismatch("a number"))?),
+        (Some(CsvType::Boolean), _) => Value::Bool(s.parse().map_err(|_| mismatch("a boolean"))?),
+        (Some(CsvType::Json), _) => serde_json::from_str(s).map_err(|_| mismatch("JSON"))?,
+        (None, CsvTypes::Json) => match serde_json::from_str(s) {
+            // Avoid parsing strings, since this will have the effect of stripping the outer
+            // quotes...
+            Err(_) | Ok(Value::String(_)) => Value::String(s.to_string()),
+            // ...but it's convenient to parse anything else that looks like JSON.
+            Ok(value) => value,
+        },
+        (None, CsvTypes::Infer) => match s.parse::<serde_json::Number>() {
+            Ok(n) if survives_javascript(s) => Value::Number(n),
+            _ => match s {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                _ => Value::String(s.to_string()),
+            },
+        },
+    })
+}
+
+/// Whether the number `s` is written the same way after a round trip through JavaScript, which
+/// rules out the likes of 1e3, 1.50, 1.0, -0, and IDs too big to be represented exactly.
+fn survives_javascript(s: &str) -> bool {
+    s.parse::<f64>()
+        .is_ok_and(|f| f.to_string() == s && (s == "0" || (1e-6..2f64.powi(53)).contains(&f.abs())))
+}
+
+/// Parse XML in to a JSON string. The root element becomes an object with a single key, its name.
+/// Attributes are keyed by `@name` and text by `#text`, elements which are repeated (or named in
+/// `arrays`) become arrays, and an element with nothing but text becomes a string.
+pub fn xml(s: &str, arrays: &[String]) -> Result<String> {
+    let mut reader = quick_xml::Reader::from_str(s);
+    let value = xml_value(&mut reader, arrays).map_err(|err| {
+        let offset = if let Some(quick_xml::Error::Syntax(_)) = err.downcast_ref() {
+            reader.error_position()
+        } else {
+            reader.buffer_position()
+        };
+        Error::new(
+            Format::Xml,
+            &err,
+            s,
+            usize::try_from(offset).unwrap_or(usize::MAX),
+        )
+    })?;
+    Ok(value.to_string())
+}
+
+/// An element which is still being read.
+#[derive(Default)]
+struct Element {
+    name: String,
+    obj: Map<String, Value>,
+    text: String,
+}
+
+impl Element {
+    fn start(e: &quick_xml::events::BytesStart) -> Result<Element, quick_xml::Error> {
+        let mut obj = Map::new();
+        for attr in e.attributes() {
+            let attr = attr?;
+            obj.insert(
+                format!("@{}", String::from_utf8_lossy(attr.key.as_ref())),
+                Value::String(attr.unescape_value()?.into_owned()),
+            );
+        }
+        Ok(Element {
+            name: String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+            obj,
+            text: String::new(),
+        })
+    }
+
+    /// Add `child` to this element, making an array if there's already one with the same name.
+    fn push(&mut self, mut child: Element, arrays: &[String]) {
+        let name = std::mem::take(&mut child.name);
+        let value = child.finish();
+        match self.obj.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => *existing = Value::Array(vec![existing.take(), value]),
+            None if arrays.contains(&name) => {
+                self.obj.insert(name, Value::Array(vec![value]));
+            }
+            None => {
+                self.obj.insert(name, value);
+            }
+        }
+    }
+
+    fn finish(mut self) -> Value {
+        let text = self.text.trim();
+        match (self.obj.is_empty(), text.is_empty()) {
+            (true, true) => Value::Null,
+            (true, false) => Value::String(text.to_string()),
+            (false, true) => Value::Object(self.obj),
+            (false, false) => {
+                self.obj
+                    .insert("#text".to_string(), Value::String(text.to_string()));
+                Value::Object(self.obj)
+            }
+        }
+    }
+}
+
+fn xml_value(reader: &mut quick_xml::Reader<&[u8]>, arrays: &[String]) -> Result<Value> {
+    use quick_xml::events::Event;
+
+    // The document itself is the outermost element.
+    let mut stack = vec![Element::default()];
+    loop {
+        match reader.read_event()? {
+            Event::Start(_) | Event::Empty(_) if stack.len() == 1 && !stack[0].obj.is_empty() => {
+                bail!("more than one root element");
+            }
+            Event::Start(e) => stack.push(Element::start(&e)?),
+            Event::Empty(e) => {
+                let child = Element::start(&e)?;
+                stack.last_mut().expect("document").push(child, arrays);
+            }
+            Event::End(_) => {
+                let child = stack.pop().expect("element");
+                stack.last_mut().expect("document").push(child, arrays);
+            }
+            Event::Text(e) => stack.last_mut().expect("document").text += &e.decode()?,
+            Event::CData(e) => stack.last_mut().expect("document").text += &e.decode()?,
+            Event::GeneralRef(e) => {
+                let text = &mut stack.last_mut().expect("document").text;
+                if let Some(c) = e.resolve_char_ref()? {
+                    text.push(c);
+                } else {
+                    let name = e.decode()?;
+                    text.push_str(
+                        quick_xml::escape::resolve_predefined_entity(&name)
+                            .with_context(|| format!("unrecognized entity &{name};"))?,
+                    );
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if let Some(unclosed) = stack.get(1) {
+        bail!("unclosed element <{}>", unclosed.name);
+    }
+    Ok(Value::Object(stack.swap_remove(0).obj))
+}
+
+/// Parse a single MessagePack value in to a JSON string.
+pub fn msgpack(mut input: &[u8]) -> Result<String> {
+    let value = rmpv::decode::read_value(&mut input).context("parsing MessagePack")?;
+    ensure!(
+        input.is_empty(),
+        "parsing MessagePack: unexpected bytes after the end of the value, try --stream"
+    );
+    Ok(from_msgpack(value).to_string())
+}
+
+/// Parse a single CBOR value in to a JSON string.
+pub fn cbor(mut input: &[u8]) -> Result<String> {
+    let value = ciborium::from_reader(&mut input).context("parsing CBOR")?;
+    ensure!(
+        input.is_empty(),
+        "parsing CBOR: unexpected bytes after the end of the value, try --stream"
+    );
+    Ok(from_cbor(value).to_string())
+}
+
+/// Convert a float to JSON, where NaN and infinity are null, as with `JSON.stringify`.
+fn from_f64(f: f64) -> Value {
+    serde_json::Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+/// Binary data becomes an array of bytes, and map keys which aren't strings are written as JSON.
+fn from_msgpack(value: rmpv::Value) -> Value {
+    match value {
+        rmpv::Value::Nil => Value::Null,
+        rmpv::Value::Boolean(b) => Value::Bool(b),
+        rmpv::Value::Integer(n) => n
+            .as_u64()
+            .map(Value::from)
+            .or_else(|| n.as_i64().map(Value::from))
+            .unwrap_or(Value::Null),
+        rmpv::Value::F32(f) => from_f64(f.into()),
+        rmpv::Value::F64(f) => from_f64(f),
+        rmpv::Value::String(s) => Value::String(String::from_utf8_lossy(s.as_bytes()).into_owned()),
+        rmpv::Value::Binary(bytes) => bytes.into(),
+        rmpv::Value::Array(values) => values.into_iter().map(from_msgpack).collect(),
+        rmpv::Value::Map(entries) => entries
+            .into_iter()
+            .map(|(k, v)| (json_key(from_msgpack(k)), from_msgpack(v)))
+            .collect(),
+        rmpv::Value::Ext(typ, data) => serde_json::json!({ "type": typ, "data": data }),
+    }
+}
+
+/// Like `from_msgpack`, apart from tags, which are dropped.
+fn from_cbor(value: ciborium::Value) -> Value {
+    match value {
+        ciborium::Value::Bool(b) => Value::Bool(b),
+        ciborium::Value::Integer(n) => {
+            let n = i128::from(n);
+            serde_json::Number::from_i128(n).map_or_else(
+                // Beyond 64 bits, which is beyond what JavaScript can represent exactly anyway.
+                #[expect(clippy::cast_precision_loss)]
+                || from_f64(n as f64),
+                Value::Number,
+            )
+        }
+        ciborium::Value::Float(f) => from_f64(f),
+        ciborium::Value::Text(s) => Value::String(s),
+        ciborium::Value::Bytes(bytes) => bytes.into(),
+        ciborium::Value::Tag(_, value) => from_cbor(*value),
+        ciborium::Value::Array(values) => values.into_iter().map(from_cbor).collect(),
+        ciborium::Value::Map(entries) => entries
+            .into_iter()
+            .map(|(k, v)| (json_key(from_cbor(k)), from_cbor(v)))
+            .collect(),
+        _ => Value::Null,
+    }
+}
+
+fn json_key(key: Value) -> String {
+    match key {
+        Value::String(s) => s,
+        _ => key.to_string(),
+    }
+}
+
+/// Parse JSON Lines (or any sequence of concatenated JSON values) in to a JSON array string.
+pub fn jsonl(s: &str) -> Result<String> {
+    Ok(serde_json::Deserializer::from_str(s)
+        .into_iter::<Value>()
+        .collect::<Result<Value, _>>()
+        .map_err(|err| Error::json(Format::Jsonl, &err, s))?
+        .to_string())
+}
+
+/// Split plain text in to lines, each as a JSON string.
+pub fn lines(r: impl BufRead) -> impl Iterator<Item = Result<String>> {
+    r.lines()
+        .map(|line| Ok(Value::String(line.context("reading line")?).to_string()))
+}
+
+/// Parse JSON Lines (or any sequence of concatenated JSON values) in to JSON strings, one value at
+/// a time.
+pub fn jsonl_records(r: impl Read) -> impl Iterator<Item = Result<String>> {
+    serde_json::Deserializer::from_reader(r)
+        .into_iter::<Value>()
+        .map(|value| Ok(value.context("parsing JSON Lines")?.to_string()))
+}
+
+/// Parse CSV in to JSON strings, one row at a time.
+pub fn csv_records<R: Read>(
+    r: R,
+    dialect: &Dialect,
+) -> Result<impl Iterator<Item = Result<String>> + use<R>> {
+    let mut reader = dialect.reader(r);
+    let headers = csv_headers(&mut reader)?;
+    let dialect = dialect.clone();
+    Ok(reader.into_records().map(move |record| {
+        csv_row(&dialect, headers.as_ref(), &record?)
+            .map(|row| row.to_string())
+            .map_err(|message| anyhow!("parsing CSV: {message}"))
+    }))
+}
+
+/// Split `r` in to records according to `format`. Plain text (no format) is split in to lines.
+pub fn records(
+    format: Option<Format>,
+    mut r: impl BufRead + Send + 'static,
+    options: &Options,
+) -> Result<Records> {
+    Ok(match format {
+        None => Box::new(lines(r)),
+        Some(Format::Json | Format::Jsonl) => Box::new(jsonl_records(r)),
+        Some(format @ (Format::Csv | Format::Tsv)) => {
+            Box::new(csv_records(r, &options.dialect(format))?)
+        }
+        // Each YAML document is a record, but a document can't be parsed until it's been read in
+        // full, so the whole input is read up front.
+        Some(Format::Yaml) => {
+            let mut s = String::new();
+            r.read_to_string(&mut s)?;
+            Box::new(
+                yaml_documents(&s)?
+                    .into_iter()
+                    .map(|doc| Ok(doc.to_string())),
+            )
+        }
+        // These formats can't be parsed a record at a time, so the whole input is one record.
+        Some(format @ (Format::Toml | Format::Json5 | Format::Xml)) => {
+            let mut input = Vec::new();
+            r.read_to_end(&mut input)?;
+            Box::new(std::iter::once(format.parse(&input, options)))
+        }
+        // Binary formats are a sequence of values, each a record.
+        Some(format @ (Format::MessagePack | Format::Cbor)) => {
+            Box::new(std::iter::from_fn(move || match r.fill_buf() {
+                Ok([]) => None,
+                Ok(_) => Some(
+                    match format {
+                        Format::MessagePack => rmpv::decode::read_value(&mut r)
+                            .map(from_msgpack)
+                            .map_err(anyhow::Error::from),
+                        _ => ciborium::from_reader(&mut r)
+                            .map(from_cbor)
+                            .map_err(anyhow::Error::from),
+                    }
+                    .map(|value| value.to_string())
+                    .with_context(|| format!("parsing {format}")),
+                ),
+                Err(err) => Some(Err(err.into())),
+            }))
+        }
+    })
 }
@@ -1,10 +1,16 @@
-use std::{io::IsTerminal, sync::LazyLock};
+use std::{
+    borrow::Cow,
+    io::{IsTerminal, Write},
+    sync::LazyLock,
+};
 
-use anyhow::{Context, Error, Result, anyhow, bail};
+use anyhow::{Context, Error, Result, anyhow, bail, ensure};
 use indexmap::IndexSet;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
+use crate::parse;
+
 const TAB_WIDTH: usize = 2;
 
 fn normal(color: Color) -> ColorSpec {
@@ -23,6 +29,7 @@ static KEY: LazyLock<ColorSpec> = LazyLock::new(|| normal(Color::Blue));
 static STR: LazyLock<ColorSpec> = LazyLock::new(|| normal(Color::Green));
 static HEADER: LazyLock<ColorSpec> = LazyLock::new(|| bold(Color::Blue));
 static ERR: LazyLock<ColorSpec> = LazyLock::new(|| bold(Color::Red));
+static NOTE: LazyLock<ColorSpec> = LazyLock::new(|| bold(Color::Cyan));
 
 fn with_color<W, F, E>(w: &mut W, color: &ColorSpec, mut f: F) -> Result<()>
 where
@@ -81,7 +88,9 @@ fn write_json(w: &mut impl WriteColor, depth: usize, value: &Value) -> Result<()
 }
 
 fn write_yaml_flow_string(w: &mut impl WriteColor, s: &str) -> Result<()> {
-    if s.starts_with(char::is_whitespace)
+    // An empty plain scalar is null.
+    if s.is_empty()
+        || s.starts_with(char::is_whitespace)
         || s.ends_with(char::is_whitespace)
         // Indicator characters
         || s.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
@@ -106,7 +115,7 @@ fn write_yaml_block_string(w: &mut impl WriteColor, depth: usize, s: &str) -> Re
         write!(w, "{TAB_WIDTH}")?;
     }
     for line in s.lines() {
-        write!(w, "\n{:indent$}{}", "", line, indent = depth * TAB_WIDTH,)?;
+        write!(w, "\n{:indent$}{}", "", line, indent = depth * TAB_WIDTH)?;
     }
     Ok(())
 }
@@ -245,7 +254,8 @@ fn write_toml<'a>(
             let values = obj.values().filter(|v| !v.is_null()).collect::<Vec<_>>();
             values.len() > 1 || (values.len() == 1 && should_nest(values[0]))
         } else if let Value::Array(arr) = value {
-            arr.iter().all(Value::is_object)
+            // An empty array of tables would be no tables at all, losing the key.
+            !arr.is_empty() && arr.iter().all(Value::is_object)
         } else {
             false
         }
@@ -401,6 +411,23 @@ pub fn yaml(w: &mut impl WriteColor, value: &Value) -> Result<()> {
     Ok(())
 }
 
+/// Print an array as a stream of YAML documents, one per element. Any other value is printed as a
+/// single document.
+pub fn yaml_docs(w: &mut impl WriteColor, value: &Value) -> Result<()> {
+    match value {
+        Value::Array(docs) => {
+            for (i, doc) in docs.iter().enumerate() {
+                if i > 0 {
+                    writeln!(w, "---")?;
+                }
+                yaml(w, doc)?;
+            }
+            Ok(())
+        }
+        _ => yaml(w, value),
+    }
+}
+
 pub fn toml(w: &mut impl WriteColor, value: &Value) -> Result<()> {
     write_toml(w, &mut Vec::new(), value)?;
     writeln!(w)?;
@@ -413,36 +440,276 @@ pub fn json5(w: &mut impl WriteColor, value: &Value) -> Result<()> {
     Ok(())
 }
 
-pub fn csv(w: &mut impl WriteColor, value: &Value) -> Result<()> {
-    let rows = value
-        .as_array()
-        .context("expected array")?
-        .iter()
-        .map(|row| row.as_object().context("expected object"))
-        .collect::<Result<Vec<_>>>()?;
+pub fn msgpack(w: &mut impl Write, value: &Value) -> Result<()> {
+    rmp_serde::encode::write(w, value)?;
+    Ok(())
+}
+
+pub fn cbor(w: &mut impl Write, value: &Value) -> Result<()> {
+    ciborium::into_writer(value, w)?;
+    Ok(())
+}
+
+/// Print `value` as XML, in the form `parse::xml` reads. An object with a single key is the root
+/// element, and anything else is wrapped in a `root` element, with the elements of an array each
+/// an `item`.
+pub fn xml(w: &mut impl WriteColor, value: &Value) -> Result<()> {
+    let items;
+    let (name, value) = match value {
+        Value::Object(obj) if obj.len() == 1 && !obj.keys().any(|k| is_xml_meta(k)) => {
+            let (name, value) = obj.iter().next().expect("single key");
+            (name.as_str(), value)
+        }
+        Value::Array(_) => {
+            items = Value::Object(Map::from_iter([("item".to_string(), value.clone())]));
+            ("root", &items)
+        }
+        _ => ("root", value),
+    };
+    check_xml_names(name, value)?;
+    write_xml(w, 0, name, value)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+/// Whether `key` is an attribute or text, rather than a child element.
+fn is_xml_meta(key: &str) -> bool {
+    key.starts_with('@') || key == "#text"
+}
+
+fn write_xml_text(w: &mut impl WriteColor, value: &Value) -> Result<()> {
+    let s = match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    };
+    with_color(w, &STR, |w| write!(w, "{}", quick_xml::escape::escape(&s)))
+}
+
+/// Whether `name` can name an element or attribute. Any letter is allowed, not just those the XML
+/// spec allows, which are most of them.
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || "_:".contains(c))
+        && chars.all(|c| c.is_alphanumeric() || "-._:".contains(c))
+}
 
-    if rows.is_empty() {
-        writeln!(w)?;
+/// Check that every element and attribute can be named as it would be in XML, before any of it is
+/// written.
+fn check_xml_names(name: &str, value: &Value) -> Result<()> {
+    ensure!(is_xml_name(name), "{name:?} isn't a valid XML element name");
+    match value {
+        Value::Array(arr) => arr.iter().try_for_each(|v| check_xml_names(name, v)),
+        Value::Object(obj) => obj.iter().try_for_each(|(k, v)| {
+            if let Some(attr) = k.strip_prefix('@') {
+                ensure!(
+                    is_xml_name(attr),
+                    "{attr:?} isn't a valid XML attribute name"
+                );
+                Ok(())
+            } else if is_xml_meta(k) {
+                Ok(())
+            } else {
+                check_xml_names(k, v)
+            }
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn write_xml(w: &mut impl WriteColor, depth: usize, name: &str, value: &Value) -> Result<()> {
+    if let Value::Array(arr) = value
+        && !arr.is_empty()
+    {
+        for (i, e) in arr.iter().enumerate() {
+            if i > 0 {
+                write!(w, "\n{:indent$}", "", indent = depth * TAB_WIDTH)?;
+            }
+            write_xml(w, depth, name, e)?;
+        }
+        return Ok(());
+    }
+
+    write!(w, "<")?;
+    with_color(w, &KEY, |w| write!(w, "{name}"))?;
+    let (text, children) = match value {
+        // Only an empty array gets this far, and it's written the same as null.
+        Value::Null | Value::Array(_) => (None, Vec::new()),
+        Value::Object(obj) => {
+            for (k, v) in obj {
+                if let Some(attr) = k.strip_prefix('@') {
+                    write!(w, " ")?;
+                    with_color(w, &KEY, |w| write!(w, "{attr}"))?;
+                    write!(w, "=\"")?;
+                    write_xml_text(w, v)?;
+                    write!(w, "\"")?;
+                }
+            }
+            (
+                obj.get("#text"),
+                obj.iter()
+                    .filter(|&(k, v)| !is_xml_meta(k) && v.as_array().is_none_or(|a| !a.is_empty()))
+                    .collect(),
+            )
+        }
+        _ => (Some(value), Vec::new()),
+    };
+
+    if text.is_none() && children.is_empty() {
+        write!(w, "/>")?;
         return Ok(());
     }
+    write!(w, ">")?;
+    if children.is_empty() {
+        write_xml_text(w, text.expect("text"))?;
+    } else {
+        for (k, v) in children {
+            write!(w, "\n{:indent$}", "", indent = (depth + 1) * TAB_WIDTH)?;
+            write_xml(w, depth + 1, k, v)?;
+        }
+        if let Some(text) = text {
+            write!(w, "\n{:indent$}", "", indent = (depth + 1) * TAB_WIDTH)?;
+            write_xml_text(w, text)?;
+        }
+        write!(w, "\n{:indent$}", "", indent = depth * TAB_WIDTH)?;
+    }
+    write!(w, "</")?;
+    with_color(w, &KEY, |w| write!(w, "{name}"))?;
+    write!(w, ">")?;
+    Ok(())
+}
+
+/// Print an array of objects as CSV, with a header row, or if `dialect` has no header, an array of
+/// arrays.
+pub fn csv(w: &mut impl WriteColor, value: &Value, dialect: &parse::Dialect) -> Result<()> {
+    let mut csv = Csv::new(w, dialect);
+    csv.write(value)?;
+    csv.finish()
+}
+
+/// Writes the rows of one or more results as CSV, under the header of the first, so that results
+/// can be printed as they're streamed.
+pub struct Csv<W: Write> {
+    writer: csv::Writer<W>,
+    dialect: parse::Dialect,
+    header: Option<IndexSet<String>>,
+    /// Whether there's been a result with no rows, which is printed as an empty line if there are
+    /// no rows at all.
+    empty: bool,
+}
+
+impl<W: Write> Csv<W> {
+    pub fn new(w: W, dialect: &parse::Dialect) -> Self {
+        Self {
+            writer: dialect.writer(w),
+            dialect: dialect.clone(),
+            header: None,
+            empty: false,
+        }
+    }
 
-    let header: IndexSet<_> = rows.iter().flat_map(|&row| row.keys()).collect();
-
-    let mut writer = csv::Writer::from_writer(w);
-    writer.write_record(&header)?;
-    for row in rows {
-        writer.write_record(header.iter().map(|&col| {
-            row.get(col).map_or(String::new(), |v| match v {
-                // Write strings as they are, the CSV writer will take care of quoting...
-                Value::String(s) => s.to_owned(),
-                // ...but serialise anything else to a string.
-                _ => v.to_string(),
+    pub fn write(&mut self, value: &Value) -> Result<()> {
+        let rows = value.as_array().context("expected array")?;
+
+        if !self.dialect.header {
+            for row in rows {
+                self.writer
+                    .write_record(row.as_array().context("expected array")?.iter().map(field))?;
+            }
+            self.writer.flush()?;
+            return Ok(());
+        }
+
+        let rows = rows
+            .iter()
+            .map(|row| {
+                let row = row.as_object().context("expected object")?;
+                Ok(if self.dialect.flatten {
+                    let mut flat = Map::new();
+                    for (key, value) in row {
+                        flatten(&mut flat, key.clone(), value);
+                    }
+                    Cow::Owned(flat)
+                } else {
+                    Cow::Borrowed(row)
+                })
             })
-        }))?;
+            .collect::<Result<Vec<_>>>()?;
+
+        if rows.is_empty() {
+            self.empty = true;
+            return Ok(());
+        }
+
+        let header = if let Some(header) = &self.header {
+            // The header has already been written, so there's no room for new columns.
+            if let Some(col) = rows
+                .iter()
+                .flat_map(|row| row.keys())
+                .find(|&col| !header.contains(col))
+            {
+                bail!("column {col} isn't in the header of the first result");
+            }
+            header
+        } else {
+            let header: IndexSet<_> = rows.iter().flat_map(|row| row.keys()).cloned().collect();
+            self.writer.write_record(&header)?;
+            self.header.insert(header)
+        };
+
+        for row in &rows {
+            self.writer.write_record(
+                header
+                    .iter()
+                    .map(|col| row.get(col).map_or(String::new(), field)),
+            )?;
+        }
+        self.writer.flush()?;
+
+        Ok(())
     }
-    writer.flush()?;
 
-    Ok(())
+    pub fn finish(self) -> Result<()> {
+        let mut w = self
+            .writer
+            .into_inner()
+            .map_err(csv::IntoInnerError::into_error)?;
+        if self.empty && self.header.is_none() {
+            writeln!(w)?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// Insert `value` in to `flat` at `key`, or if it's a non-empty object or array, each of its
+/// elements at `key.0`, `key.1`, etc.
+fn flatten(flat: &mut Map<String, Value>, key: String, value: &Value) {
+    match value {
+        Value::Object(obj) if !obj.is_empty() => {
+            for (k, v) in obj {
+                flatten(flat, format!("{key}.{k}"), v);
+            }
+        }
+        Value::Array(arr) if !arr.is_empty() => {
+            for (i, v) in arr.iter().enumerate() {
+                flatten(flat, format!("{key}.{i}"), v);
+            }
+        }
+        _ => {
+            flat.insert(key, value.clone());
+        }
+    }
+}
+
+fn field(v: &Value) -> String {
+    match v {
+        // Write strings as they are, the CSV writer will take care of quoting...
+        Value::String(s) => s.to_owned(),
+        // ...but serialise anything else to a string.
+        _ => v.to_string(),
+    }
 }
 
 pub fn error(w: &mut impl WriteColor, err: &Error) -> Result<()> {
@@ -451,6 +718,58 @@ pub fn error(w: &mut impl WriteColor, err: &Error) -> Result<()> {
     Ok(())
 }
 
+pub fn note(w: &mut impl WriteColor, msg: &str) -> Result<()> {
+    with_color(w, &NOTE, |w| write!(w, "note"))?;
+    writeln!(w, ": {msg}")?;
+    Ok(())
+}
+
+/// Point at `column` (counting from 1) of `text`, which is found at `location`.
+pub fn snippet(
+    w: &mut impl WriteColor,
+    location: &str,
+    text: &str,
+    line: usize,
+    column: usize,
+) -> Result<()> {
+    let gutter = " ".repeat(line.to_string().len());
+    with_color(w, &NOTE, |w| write!(w, "{gutter}--> "))?;
+    writeln!(w, "{location}")?;
+    with_color(w, &NOTE, |w| writeln!(w, "{gutter} |"))?;
+    with_color(w, &NOTE, |w| write!(w, "{line} | "))?;
+    writeln!(w, "{text}")?;
+    with_color(w, &NOTE, |w| write!(w, "{gutter} | "))?;
+    // Preserve tabs so that the caret lines up.
+    let indent: String = text
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    with_color(w, &ERR, |w| writeln!(w, "{indent}^"))?;
+    Ok(())
+}
+
+/// Point at where parsing input went wrong, and suggest another format if it looks like one.
+pub fn parse_error(w: &mut impl WriteColor, err: &parse::Error) -> Result<()> {
+    snippet(
+        w,
+        &format!(
+            "{}:{}:{} (byte {})",
+            err.name, err.line, err.column, err.offset
+        ),
+        &err.text,
+        err.line,
+        err.column,
+    )?;
+    if let Some(format) = err.hint {
+        note(
+            w,
+            &format!("this looks like {format}, try {}", format.flag()),
+        )?;
+    }
+    Ok(())
+}
+
 pub fn stdout() -> StandardStream {
     StandardStream::stdout(color_choice(&std::io::stdout()))
 }
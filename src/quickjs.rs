@@ -0,0 +1,188 @@
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, PipeWriter, Read, Write};
+
+use anyhow::Result;
+use rquickjs::loader::{Loader, Resolver};
+use rquickjs::{CaughtError, Coerced, Context, Ctx, Function, Module, Runtime};
+use url::Url;
+
+use crate::backend::{Backend, Process};
+use crate::deno;
+
+/// The name of the module the script is evaluated as, in the working directory.
+const MODULE: &str = "jsq.js";
+
+/// Defines enough of `console` for scripts to print things.
+const PRELUDE: &str = r#"
+    globalThis.console = (() => {
+        const inspect = (value) => {
+            if (typeof value === "string") {
+                return value;
+            }
+            try {
+                return JSON.stringify(value) ?? String(value);
+            } catch {
+                return String(value);
+            }
+        };
+        const write = (fd) => (...args) => __jsq_write(fd, args.map(inspect).join(" ") + "\n");
+        return {
+            log: write(1),
+            info: write(1),
+            debug: write(1),
+            error: write(2),
+            warn: write(2),
+        };
+    })();
+"#;
+
+/// [QuickJS](https://bellard.org/quickjs/), embedded, so that scripts can be run without a
+/// separate runtime installed. Only the standard library is available, along with `console`, and
+/// only local files can be imported.
+pub struct QuickJs;
+
+impl Backend for QuickJs {
+    fn typescript(&self) -> bool {
+        false
+    }
+
+    fn lines(&self) -> &'static str {
+        r"
+            async function* __jsq_lines() {
+                for (let line; (line = __jsq_read_line()) !== undefined; ) {
+                    yield JSON.parse(line);
+                }
+            }
+        "
+    }
+
+    fn spawn(&self, code: String, capture: bool) -> Result<Process> {
+        let (lines, stdin) = std::io::pipe()?;
+        let (stdout, out) = if capture {
+            let (r, w) = std::io::pipe()?;
+            (
+                Some(Box::new(r) as Box<dyn Read + Send>),
+                Box::new(w) as Box<dyn Write + Send>,
+            )
+        } else {
+            (None, Box::new(std::io::stdout()) as Box<dyn Write + Send>)
+        };
+        let (stderr, err) = std::io::pipe()?;
+        let thread = std::thread::spawn(move || run(&code, BufReader::new(lines), out, err));
+        Ok(Process::new(
+            Box::new(stdin),
+            stdout,
+            Box::new(stderr),
+            MODULE.to_string(),
+            move || thread.join().expect("joining QuickJS"),
+        ))
+    }
+}
+
+fn run(
+    code: &str,
+    lines: impl BufRead + 'static,
+    out: impl Write + 'static,
+    mut err: PipeWriter,
+) -> Result<i32> {
+    let runtime = Runtime::new()?;
+    runtime.set_loader(FileResolver, FileLoader);
+    let context = Context::full(&runtime)?;
+    context.with(|ctx| {
+        let res = (|| {
+            let out = RefCell::new(out);
+            let err = RefCell::new(err.try_clone()?);
+            ctx.globals().set(
+                "__jsq_write",
+                Function::new(ctx.clone(), move |fd: i32, s: String| {
+                    if fd == 1 {
+                        out.borrow_mut().write_all(s.as_bytes())
+                    } else {
+                        err.borrow_mut().write_all(s.as_bytes())
+                    }
+                    .map_err(rquickjs::Error::from)
+                })?,
+            )?;
+            let lines = RefCell::new(lines);
+            ctx.globals().set(
+                "__jsq_read_line",
+                Function::new(ctx.clone(), move || {
+                    let mut line = String::new();
+                    lines.borrow_mut().read_line(&mut line)?;
+                    Ok::<_, rquickjs::Error>((!line.is_empty()).then_some(line))
+                })?,
+            )?;
+            ctx.eval::<(), _>(PRELUDE)?;
+            Module::evaluate(ctx.clone(), cwd_url(MODULE)?, code)?.finish::<()>()
+        })();
+        match res {
+            Ok(()) => Ok(0),
+            Err(e) => {
+                print_uncaught(&mut err, CaughtError::from_error(&ctx, e))?;
+                Ok(1)
+            }
+        }
+    })
+}
+
+fn print_uncaught(w: &mut impl Write, err: CaughtError<'_>) -> Result<()> {
+    let msg = match err {
+        CaughtError::Exception(exception) => {
+            let name = exception
+                .get::<_, Coerced<String>>("name")
+                .map_or_else(|_| "Error".to_string(), |name| name.0);
+            format!(
+                "{name}: {}\n{}",
+                exception.message().unwrap_or_default(),
+                exception.stack().unwrap_or_default(),
+            )
+        }
+        CaughtError::Value(value) => value
+            .get::<Coerced<String>>()
+            .map_or_else(|_| format!("{value:?}"), |s| s.0),
+        CaughtError::Error(err) => err.to_string(),
+    };
+    writeln!(w, "error: Uncaught {}", msg.trim_end())?;
+    Ok(())
+}
+
+/// The URL of `name` in the working directory, which the script is evaluated relative to.
+fn cwd_url(name: &str) -> rquickjs::Result<String> {
+    Url::from_directory_path(std::env::current_dir()?)
+        .ok()
+        .and_then(|base| base.join(name).ok())
+        .map(String::from)
+        .ok_or_else(|| rquickjs::Error::new_resolving(".", name))
+}
+
+struct FileResolver;
+
+impl Resolver for FileResolver {
+    fn resolve(&mut self, _: &Ctx<'_>, base: &str, name: &str) -> rquickjs::Result<String> {
+        match Url::parse(base).and_then(|base| base.join(name)) {
+            Ok(url) if url.scheme() == "file" => Ok(url.into()),
+            _ => Err(rquickjs::Error::new_resolving_message(
+                base,
+                name,
+                "only local files can be imported by QuickJS",
+            )),
+        }
+    }
+}
+
+struct FileLoader;
+
+impl Loader for FileLoader {
+    fn load<'js>(&mut self, ctx: &Ctx<'js>, name: &str) -> rquickjs::Result<Module<'js>> {
+        let path = Url::parse(name)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| rquickjs::Error::new_loading(name))?;
+        let mut source = std::fs::read_to_string(&path)?;
+        if path.extension().is_some_and(|ext| ext == "ts") {
+            source = deno::transpile(&source)
+                .map_err(|err| rquickjs::Error::new_loading_message(name, err.to_string()))?;
+        }
+        Module::declare(ctx.clone(), name, source)
+    }
+}
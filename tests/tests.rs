@@ -1,6 +1,7 @@
 use std::{
     env::{self, consts::EXE_SUFFIX},
     io::Write,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
@@ -13,18 +14,37 @@ struct Output {
     stderr: String,
 }
 
-fn run<'a, I>(args: &[&str], stdin: &str, vars: I) -> Result<Output>
-where
-    I: IntoIterator<Item = (&'a str, &'a str)>,
-{
-    let bin = env::current_exe()?
+fn bin_dir() -> Result<PathBuf> {
+    Ok(env::current_exe()?
         .parent()
         .context("getting parent")?
         .parent()
         .context("getting parent")?
-        .join(format!("jsq{}", EXE_SUFFIX));
+        .to_path_buf())
+}
 
-    let mut child = Command::new(bin)
+fn run<'a, I>(args: &[&str], stdin: impl AsRef<[u8]>, vars: I) -> Result<Output>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    run_program(
+        &bin_dir()?.join(format!("jsq{}", EXE_SUFFIX)),
+        args,
+        stdin,
+        vars,
+    )
+}
+
+fn run_program<'a, I>(
+    program: &Path,
+    args: &[&str],
+    stdin: impl AsRef<[u8]>,
+    vars: I,
+) -> Result<Output>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut child = Command::new(program)
         .args(args)
         .envs(vars)
         .stdin(Stdio::piped())
@@ -36,13 +56,14 @@ where
         .stdin
         .take()
         .context("getting stdin")?
-        .write_all(stdin.as_bytes())?;
+        .write_all(stdin.as_ref())?;
 
     let output = child.wait_with_output()?;
 
     Ok(Output {
         status_code: output.status.code().context("getting status code")?,
-        stdout: String::from_utf8(output.stdout)?,
+        // Binary formats aren't UTF-8, but their output can still be compared this way.
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
         stderr: String::from_utf8(output.stderr)?,
     })
 }
@@ -140,15 +161,54 @@ fn test() -> Result<()> {
     assert_err!(run(&["foo"], "", [])?, "ReferenceError: foo is not defined");
 
     assert_err!(
-        run(&["return 42"], "", [])?,
-        "A 'return' statement can only be used within a function body",
+        run(&["const x = {};\nx.y.z"], "", [])?,
+        " --> SCRIPT:2:5\n  |\n2 | x.y.z\n  |     ^\n",
+    );
+
+    assert_err!(
+        run(&["let a = 1;\nundefinedFn()"], "", [])?,
+        " --> SCRIPT:2:1\n  |\n2 | undefinedFn()\n  | ^\n",
+    );
+
+    assert_ok!(run(&["return 42"], "", [])?, "42\n");
+    assert_ok!(
+        run(
+            &["-jJ", "if (!$.items) return []; $.items.length"],
+            "{}",
+            []
+        )?,
+        "[]\n",
+    );
+
+    assert_ok!(run(&["-je", "$.ok"], r#"{"ok": true}"#, [])?, "true\n");
+    assert_eq!(
+        run(&["-je", "$.ok"], r#"{"ok": false}"#, [])?.status_code,
+        1
+    );
+    assert_eq!(run(&["-jNe", "$.missing"], "{}", [])?.status_code, 1);
+    assert_eq!(run(&["-e", "throw 1"], "", [])?.status_code, 2);
+    assert_eq!(
+        run(&["-e", "-p", "$ === 'a' ? true : undefined"], "a\nb\n", [])?.status_code,
+        1
     );
+    assert_eq!(run(&["-je", "$"], "foo", [])?.status_code, 2);
 
     assert_err!(
         run(&["-j"], "foo", [])?,
         "parsing JSON: expected ident at line 1 column 2",
     );
 
+    assert_err!(
+        run(&["-j"], "{\n  a: 1,\n}", [])?,
+        concat!(
+            " --> STDIN:2:3 (byte 4)\n",
+            "  |\n",
+            "2 |   a: 1,\n",
+            "  |   ^\n",
+            "note: this looks like JSON5, try -5\n",
+        ),
+    );
+
     assert_ok!(
         run(&["-y", "$.jobs.info['runs-on']"], &publish_yaml, [])?,
         "macos-latest\n",
@@ -162,6 +222,7 @@ fn test() -> Result<()> {
 
     assert_ok!(run(&["-J", "undefined"], "", [])?, "undefined\n");
     assert_ok!(run(&["-Y", "undefined"], "", [])?, "undefined\n");
+    assert_ok!(run(&["-Y", "({ a: '' })"], "", [])?, "a: \"\"\n");
     assert_ok!(run(&["-T", "undefined"], "", [])?, "undefined\n");
     assert_ok!(run(&["-%", "undefined"], "", [])?, "undefined\n");
     assert_ok!(run(&["-C", "undefined"], "", [])?, "undefined\n");
@@ -171,6 +232,11 @@ fn test() -> Result<()> {
     assert_ok!(run(&["-%", "() => {}"], "", [])?, "undefined\n");
     assert_ok!(run(&["-C", "() => {}"], "", [])?, "undefined\n");
 
+    assert_ok!(
+        run(&["-T", "({ a: [], b: 1 })"], "", [])?,
+        "a = []\nb = 1\n"
+    );
+
     assert_eq!(
         convert("-tY", &convert("-jT", &convert("-yJ", &publish_yaml)?)?)?,
         publish_yaml
@@ -242,6 +308,20 @@ fn test() -> Result<()> {
     );
 
     assert_ok!(run(&["let x"], "", [])?, "undefined\n");
+    assert_ok!(run(&["let x = 42"], "", [])?, "42\n");
+    assert_ok!(
+        run(
+            &["if ($.length > 3) { 'long' } else { 'short' }"],
+            "foo",
+            []
+        )?,
+        "short\n",
+    );
+
+    assert_ok!(
+        run(&["-J", "Promise.resolve({ a: 1 })"], "", [])?,
+        "{\n  \"a\": 1\n}\n",
+    );
 
     assert_ok!(
         run(
@@ -255,6 +335,18 @@ fn test() -> Result<()> {
         "foo\nbar\n{\n  \"baz\": 42\n}\n",
     );
 
+    assert_ok!(
+        run(
+            &[
+                "-J",
+                r#"process.stdout.write("foo"); setTimeout(() => console.log("bar")); 42"#
+            ],
+            "",
+            []
+        )?,
+        "foo\n42\nbar\n",
+    );
+
     assert_eq!(
         convert(
             "-cJ",
@@ -271,6 +363,572 @@ fn test() -> Result<()> {
         "a,b,c\nfoo,42,true\n\"\"\"bar\"\"\",-1.23,false\n\"foo,bar\",,null\n",
     );
 
+    assert_ok!(
+        run(
+            &["-c", "--delimiter", ";", "--comment", "#", "--tsv-out"],
+            "# prices\nitem;price\n\"a;b\";1,50\n",
+            []
+        )?,
+        "item\tprice\na;b\t1,50\n",
+    );
+
+    assert_ok!(
+        run(
+            &["--tsv-in", "--no-header", "-C", "$.map(([a, b]) => [b, a])"],
+            "1\tx\n2\ty\n",
+            []
+        )?,
+        "x,1\ny,2\n",
+    );
+
+    let ids = "id,zip,amount,ok,note\n12345678901234567890,007,1.50,true,\n2,01234,3,false,[1]\n3,1.0,0.5,true,x\n";
+
+    assert_ok!(
+        run(
+            &["-c", "--csv-types", "none", "JSON.stringify($[1])"],
+            ids,
+            []
+        )?,
+        "{\"id\":\"2\",\"zip\":\"01234\",\"amount\":\"3\",\"ok\":\"false\",\"note\":\"[1]\"}\n",
+    );
+
+    assert_ok!(
+        run(
+            &[
+                "-c",
+                "--csv-types",
+                "infer",
+                "--empty-null",
+                "JSON.stringify($)"
+            ],
+            ids,
+            []
+        )?,
+        concat!(
+            r#"[{"id":"12345678901234567890","zip":"007","amount":"1.50","ok":true,"note":null},"#,
+            r#"{"id":2,"zip":"01234","amount":3,"ok":false,"note":"[1]"},"#,
+            r#"{"id":3,"zip":"1.0","amount":0.5,"ok":true,"note":"x"}]"#,
+            "\n",
+        ),
+    );
+
+    assert_ok!(
+        run(
+            &[
+                "-c",
+                "--csv-schema",
+                "id:string,amount:number",
+                "JSON.stringify($[1])"
+            ],
+            ids,
+            []
+        )?,
+        "{\"id\":\"2\",\"zip\":\"01234\",\"amount\":3,\"ok\":false,\"note\":[1]}\n",
+    );
+
+    assert_err!(
+        run(&["-c", "--csv-schema", "ok:number"], ids, [])?,
+        "parsing CSV: column ok: expected a number, got \"true\"",
+    );
+
+    let nested = r#"[{"a":1,"b":{"c":true,"d":[2,3]}},{"a":4,"b":{"d":[5]}}]"#;
+
+    assert_ok!(
+        run(&["-j", "--flatten", "-C"], nested, [])?,
+        "a,b.c,b.d.0,b.d.1\n1,true,2,3\n4,,5,\n",
+    );
+
+    assert_ok!(
+        run(
+            &["-c", "--flatten", "JSON.stringify($)"],
+            "a,b.c,b.d.0,b.d.1\n1,true,2,3\n4,,5,\n",
+            []
+        )?,
+        format!("{nested}\n"),
+    );
+
+    assert_ok!(
+        run(
+            &["-c", "--quote", "'", "--escape", r"\", "-C"],
+            "a\n'it\\'s'\n'a,b'\n",
+            []
+        )?,
+        "a\n'it\\'s'\n'a,b'\n",
+    );
+
+    let rss = concat!(
+        "<rss version=\"2.0\">\n",
+        "  <channel>\n",
+        "    <title>News &amp; views</title>\n",
+        "    <item>\n",
+        "      <guid isPermaLink=\"false\">1</guid>\n",
+        "      <title>First</title>\n",
+        "    </item>\n",
+        "  </channel>\n",
+        "</rss>\n",
+    );
+
+    assert_ok!(
+        run(&["-x", "JSON.stringify($)"], rss, [])?,
+        concat!(
+            r#"{"rss":{"@version":"2.0","channel":{"title":"News & views","#,
+            r##""item":{"guid":{"@isPermaLink":"false","#text":"1"},"title":"First"}}}}"##,
+            "\n",
+        ),
+    );
+
+    assert_ok!(
+        run(
+            &["-x", "--xml-array", "item", "$.rss.channel.item.length"],
+            rss,
+            []
+        )?,
+        "1\n",
+    );
+
+    assert_ok!(run(&["-xX"], rss, [])?, rss);
+
+    assert_ok!(
+        run(&["-X", "[1, null]"], "", [])?,
+        "<root>\n  <item>1</item>\n  <item/>\n</root>\n",
+    );
+
+    assert_ok!(run(&["-X", "({ a: [] })"], "", [])?, "<a/>\n");
+
+    assert_err!(
+        run(&["-X", "({ 'my key': 1, '2x': 'a<b' })"], "", [])?,
+        "\"my key\" isn't a valid XML element name",
+    );
+
+    assert_err!(
+        run(&["-x"], "<a>1</a><b>2</b>", [])?,
+        "more than one root element",
+    );
+
+    assert_ok!(
+        run(
+            &["-m", "JSON.stringify($)"],
+            b"\x82\xa1a\x93\x01\xff\xc3\xa1b\xc4\x02hi",
+            []
+        )?,
+        "{\"a\":[1,-1,true],\"b\":[104,105]}\n",
+    );
+
+    assert_ok!(
+        run(&["-jM"], r#"{"a": [1, -1, true]}"#, [])?,
+        String::from_utf8_lossy(b"\x81\xa1a\x93\x01\xff\xc3"),
+    );
+
+    assert_ok!(
+        run(
+            &["--cbor-in", "--stream", "$.a"],
+            b"\xa1\x61a\x01\xa1\x61a\xc1\x02",
+            []
+        )?,
+        "1\n2\n",
+    );
+
+    assert_ok!(
+        run(&["-j", "--cbor-out"], r#"{"a": "b"}"#, [])?,
+        String::from_utf8_lossy(b"\xa1\x61a\x61b"),
+    );
+
+    assert_ok!(
+        run(&["-lJ", "$.map(r => r.a)"], "{\"a\":1}\n{\"a\":2}\n", [])?,
+        "[\n  1,\n  2\n]\n",
+    );
+
+    assert_ok!(
+        run(
+            &["-lJ", "--stream", r#"console.log("foo"); $.a"#],
+            "{\"a\":1}{\"a\":[2]}",
+            []
+        )?,
+        "foo\n1\nfoo\n[\n  2\n]\n",
+    );
+
+    assert_ok!(
+        run(&["--stream", "$.length"], "a\nbb\nccc", [])?,
+        "1\n2\n3\n"
+    );
+
+    assert_ok!(
+        run(
+            &["-cJ", "--stream", "$.b"],
+            "a,b\n1,foo\n2,\"bar,baz\"\n",
+            []
+        )?,
+        "\"foo\"\n\"bar,baz\"\n",
+    );
+
+    assert_ok!(
+        run(&["-cC", "--stream", "[$]"], "a,b\n1,2\n3,4\n", [])?,
+        "a,b\n1,2\n3,4\n",
+    );
+
+    // The first row has already been printed by the time the second turns out not to fit.
+    let res = run(&["-lC", "--stream", "[$]"], "{\"a\":1}\n{\"b\":2}\n", [])?;
+    assert_eq!(res.stdout, "a\n1\n");
+    assert!(
+        res.stderr
+            .contains("column b isn't in the header of the first result")
+    );
+
+    assert_eq!(run(&["-cT", "--stream", "$"], "", [])?.status_code, 2);
+    assert_eq!(run(&["-X", "-p", "$"], "", [])?.status_code, 2);
+
+    assert_ok!(
+        run(
+            &[
+                "--stream",
+                r#"import { double } from "./tests/lib.js"; double(Number($))"#
+            ],
+            "1\n2\n",
+            []
+        )?,
+        "2\n4\n",
+    );
+
+    assert_ok!(
+        run(
+            &["-p", "$.length > 1 ? `${$i}: ${$}` : undefined"],
+            "a\nbb\nccc\n",
+            []
+        )?,
+        "2: bb\n3: ccc\n",
+    );
+
+    assert_ok!(
+        run(&["-n", "console.log($i, $.toUpperCase())"], "a\nb\n", [])?,
+        "1 A\n2 B\n",
+    );
+
+    assert_ok!(
+        run(
+            &["-lpJ", "$.a > 1 ? $ : undefined"],
+            "{\"a\":1}\n{\"a\":2}\n",
+            []
+        )?,
+        "{\n  \"a\": 2\n}\n",
+    );
+
+    assert_err!(
+        run(&["-l"], "{\"a\":1}\n{\"a\":", [])?,
+        "parsing JSON Lines: EOF while parsing a value at line 2 column 5",
+    );
+
+    assert_ok!(run(&["$.package.name", "Cargo.toml"], "", [])?, "jsq\n");
+
+    assert_ok!(
+        run(
+            &[
+                "-J",
+                "$.map(x => Object.keys(x)[0])",
+                "Cargo.toml",
+                ".github/workflows/publish.yaml"
+            ],
+            "",
+            []
+        )?,
+        "[\n  \"package\",\n  \"on\"\n]\n",
+    );
+
+    assert_ok!(
+        run(
+            &[
+                "--keyed",
+                "Object.keys($).join()",
+                "Cargo.toml",
+                "tests/test.js"
+            ],
+            "",
+            []
+        )?,
+        "Cargo.toml,tests/test.js\n",
+    );
+
+    assert_ok!(
+        run(&["-p", "$i === 5 ? $ : undefined", "tests/test.js"], "", [])?,
+        "40 + 2;\n",
+    );
+
+    assert_ok!(
+        run(&["-f", "tests/test.js", "Cargo.toml"], "", [])?,
+        "0\n1\n2\n3\n4\n42\n",
+    );
+
+    assert_err!(
+        run(
+            &["-f", "tests/test.js", "-i", "Cargo.toml", "README.md"],
+            "",
+            []
+        )?,
+        "--in-place can't be used with FILEs",
+    );
+
+    assert_ok!(
+        run(
+            &["-J", "[$args, $2]", "Cargo.toml", "--", "foo", "bar"],
+            "",
+            []
+        )?,
+        "[\n  [\n    \"foo\",\n    \"bar\"\n  ],\n  \"bar\"\n]\n",
+    );
+
+    assert_ok!(run(&["-a", "$.package.name"], &cargo_toml, [])?, "jsq\n");
+
+    assert_ok!(
+        run(&["-a", "$.jobs.info['runs-on']"], &publish_yaml, [])?,
+        "macos-latest\n",
+    );
+
+    assert_ok!(run(&["-a", "$[1].b"], "a,b\n1,foo\n2,bar\n", [])?, "bar\n");
+
+    assert_ok!(run(&["-a", "typeof $"], "hello world", [])?, "string\n");
+
+    assert_ok!(
+        run(
+            &["-a", "typeof $"],
+            "Hello, world. Nice day\nfoo, bar baz\nand, so on\n",
+            []
+        )?,
+        "string\n",
+    );
+
+    assert_eq!(
+        run(&["-a", "--report-format", "$.length"], "[1, 2]", [])?,
+        Output {
+            status_code: 0,
+            stdout: "2\n".to_string(),
+            stderr: "note: reading STDIN as JSON\n".to_string(),
+        },
+    );
+
+    // Too big for the sample used to detect the format when streaming to be valid JSON.
+    let big = format!("[\n{}\n]\n", vec!["  1"; 1 << 15].join(",\n"));
+    let res = run(&["-a", "--stream", "-n", "$"], &big, [])?;
+    assert_eq!(res.status_code, 0);
+    assert!(res.stderr.contains(
+        "note: reading STDIN as plain text, since its format couldn't be detected from the first \
+         64 KiB, try --FORMAT-in"
+    ));
+
+    let manifests = "kind: Service\nname: foo\n---\nkind: Deployment\nname: bar\n";
+
+    assert_err!(
+        run(&["-y"], manifests, [])?,
+        "parsing YAML: deserializing from YAML containing more than one document is not supported",
+    );
+
+    assert_ok!(run(&["-yd", "$[1].name"], manifests, [])?, "bar\n");
+
+    assert_ok!(run(&["-ydY"], manifests, [])?, manifests);
+
+    assert_ok!(
+        run(
+            &["-ydY", "$.filter((m) => m.kind === 'Deployment')"],
+            manifests,
+            []
+        )?,
+        "kind: Deployment\nname: bar\n",
+    );
+
+    assert_ok!(
+        run(
+            &["-y", "--stream", "-dY", "({ ...$, i: $i })"],
+            manifests,
+            []
+        )?,
+        "kind: Service\nname: foo\ni: 1\n---\nkind: Deployment\nname: bar\ni: 2\n",
+    );
+
+    let dir = tempfile::tempdir()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let greet = dir.path().join("greet.ts");
+        std::fs::write(&greet, "#!/usr/bin/env -S jsq --args -f\n`Hello, ${$1}!`\n")?;
+        std::fs::set_permissions(&greet, std::fs::Permissions::from_mode(0o755))?;
+        let path = env::join_paths(
+            [bin_dir()?]
+                .into_iter()
+                .chain(env::split_paths(&env::var_os("PATH").unwrap_or_default())),
+        )?;
+        assert_ok!(
+            run_program(&greet, &["world"], "", [("PATH", path.to_str().unwrap())])?,
+            "Hello, world!\n",
+        );
+    }
+
+    let toml = dir.path().join("config.toml");
+    std::fs::write(
+        &toml,
+        "# Settings\n[server]\nport = 8080 # default\nhosts = [ \"a\" ]\n",
+    )?;
+    assert_ok!(
+        run(
+            &[
+                "-i",
+                toml.to_str().unwrap(),
+                "$.server.port++, $.server.hosts.push('b'), $"
+            ],
+            "",
+            [],
+        )?,
+        "",
+    );
+    assert_eq!(
+        std::fs::read_to_string(&toml)?,
+        "# Settings\n[server]\nport = 8081 # default\nhosts = [ \"a\", \"b\" ]\n",
+    );
+
+    // Numbers JavaScript can't tell apart from what it gets back are left alone.
+    let numbers = "title = \"x\"\nratio = 1.0\nbig = 1e3\nid = 9007199254740993\n";
+    std::fs::write(&toml, numbers)?;
+    assert_ok!(
+        run(&["-i", toml.to_str().unwrap(), "$.title = 'y', $"], "", [])?,
+        "",
+    );
+    assert_eq!(
+        std::fs::read_to_string(&toml)?,
+        numbers.replace("\"x\"", "\"y\""),
+    );
+
+    let yaml = dir.path().join("ci.yml");
+    std::fs::write(
+        &yaml,
+        "# CI\nimage: 'node:20' # pinned\nsteps: [test, build]\n",
+    )?;
+    assert_ok!(
+        run(
+            &["-i", yaml.to_str().unwrap(), "$.image = 'node:22', $"],
+            "",
+            []
+        )?,
+        "",
+    );
+    assert_eq!(
+        std::fs::read_to_string(&yaml)?,
+        "# CI\nimage: 'node:22' # pinned\nsteps: [test, build]\n",
+    );
+    assert_ok!(
+        run(
+            &["-i", yaml.to_str().unwrap(), "$.steps.push('deploy'), $"],
+            "",
+            []
+        )?,
+        "",
+    );
+    assert_eq!(
+        std::fs::read_to_string(&yaml)?,
+        "image: node:22\nsteps:\n  - test\n  - build\n  - deploy\n",
+    );
+
+    std::fs::write(&yaml, "title: x\nratio: 1.0\nid: 9007199254740993\n")?;
+    assert_ok!(
+        run(&["-i", yaml.to_str().unwrap(), "$.title = 'y', $"], "", [])?,
+        "",
+    );
+    assert_eq!(
+        std::fs::read_to_string(&yaml)?,
+        "title: y\nratio: 1.0\nid: 9007199254740993\n",
+    );
+
+    // Implicit nulls have nowhere to put a value, so the document is reprinted.
+    for (original, script, edited) in [
+        ("key:\nother: 1\n", "$.key = 5, $", "key: 5\nother: 1\n"),
+        ("a:\n  - x\n  -\n", "$.a[1] = 'y', $", "a:\n  - x\n  - y\n"),
+        ("a: b # c\n", "$.a = '', $", "a: \"\" # c\n"),
+    ] {
+        std::fs::write(&yaml, original)?;
+        assert_ok!(run(&["-i", yaml.to_str().unwrap(), script], "", [])?, "");
+        assert_eq!(std::fs::read_to_string(&yaml)?, edited);
+    }
+
+    assert_ok!(
+        run(
+            &["--runtime", "node", "-jJ", "$.a.map((x: number) => x * 2)"],
+            r#"{ "a": [1, 2] }"#,
+            [],
+        )?,
+        "[\n  2,\n  4\n]\n",
+    );
+
+    assert_ok!(
+        run(
+            &["-p", "`${$i}: ${$}`"],
+            "foo\nbar\n",
+            [("JSQ_RUNTIME", "node")]
+        )?,
+        "1: foo\n2: bar\n",
+    );
+
+    assert_ok!(
+        run(
+            &[
+                "--arg",
+                "USER",
+                "foo",
+                "--argjson",
+                "n",
+                "[1, 2]",
+                "--slurpfile",
+                "config",
+                "Cargo.toml",
+                "-J",
+                "[$USER, $n, $config.package.name]",
+            ],
+            "",
+            [("USER", "bar")],
+        )?,
+        "[\n  \"foo\",\n  [\n    1,\n    2\n  ],\n  \"jsq\"\n]\n",
+    );
+    assert_err!(
+        run(&["--arg", "a-b", "c", "$"], "", [])?,
+        "invalid name: a-b"
+    );
+
+    assert_ok!(
+        run(&["--allow-read=.", "-jJ", "$.a"], r#"{ "a": 1 }"#, [])?,
+        "1\n",
+    );
+    assert_ok!(
+        run(
+            &["--sandbox", "--allow-env=FOO", "`${$FOO} ${typeof $BAR}`"],
+            "",
+            [("FOO", "foo"), ("BAR", "bar")],
+        )?,
+        "foo undefined\n",
+    );
+    assert_err!(
+        run(&["--runtime", "node", "1"], "", [("JSQ_SANDBOX", "true")])?,
+        "sandboxing is only supported by deno",
+    );
+
+    if cfg!(feature = "quickjs") {
+        // Without Deno on the PATH, scripts are evaluated by QuickJS instead.
+        assert_ok!(
+            run(
+                &["-jJ", "$.a.map((x: number) => x * 2)"],
+                r#"{ "a": [1, 2] }"#,
+                [("PATH", "")],
+            )?,
+            "[\n  2,\n  4\n]\n",
+        );
+
+        assert_ok!(
+            run(&["-p", "`${$i}: ${$}`"], "foo\nbar\n", [("PATH", "")])?,
+            "1: foo\n2: bar\n",
+        );
+
+        assert_err!(
+            run(&["foo"], "", [("PATH", "")])?,
+            "ReferenceError: foo is not defined",
+        );
+    }
+
     assert_ok!(
         run(&["-Js", r#"({ b: "bar", a: "foo" })"#], "", [])?,
         "{\n  \"a\": \"foo\",\n  \"b\": \"bar\"\n}\n",